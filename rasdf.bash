@@ -1,30 +1,29 @@
 # pass command to rasdf; but remove the history number on the way
 export PROMPT_COMMAND='rasdf add $PWD $(history 1 | sed -e '"'"'s/^\s*\(\S\+\s\+\)//'"'"')'
 
-# Case-insensitive but strict matching
-export RASDF_FLAGS='is'
+# Smart-case (case-insensitive unless a term has a capital) and strict matching
+export RASDF_FLAGS='Ss'
 
 z() { 
   local newdir
-  newdir=$( rasdf find -dsi $@ )
-  if [ -n "$newdir" ]; then 
+  if newdir=$( rasdf find -dsS "$@" ); then 
     printf "%s\n" "$newdir" >&2
     cd "$newdir"
   else
-    printf "Can't find matching directory\n" >&2
-    return 2
+    local status=$?
+    [ $status -eq 1 ] && printf "Can't find matching directory\n" >&2
+    return $status
   fi 
 }
 
 alias a='rasdf find -Dacl '
-alias s='rasdf find-all -ail '
-alias d='rasdf find -dsi '
-alias f='rasdf find -fsi '
+alias s='rasdf find-all -aSl '
+alias d='rasdf find -dsS '
+alias f='rasdf find -fsS '
 function v {
-  filename="$( rasdf find -fsi $* )"
-  if [ -f "$filename" ] 
+  if filename="$( rasdf find -fsS "$@" )"
     then nvim "$filename"
-    else echo cannot find "$filename" >&2
+    else echo cannot find "$*" >&2
   fi
 }
 
@@ -0,0 +1,117 @@
+//! Bookmarks: names that always find one path, whatever the scores.
+//!
+//! They are kept beside the datafile in `<datafile>.bookmarks`, one
+//! `name|path` row each, both escaped as paths are in the datafile. It
+//! is only changed under the datafile lock, and replaced by rename as
+//! the datafile is.
+
+use crate::config::Config;
+use crate::encoding::{decode_path, encode_path};
+use crate::error::RasdfError;
+use crate::{create_temp_file, sync_parent_dir};
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// The bookmarks file for a datafile: same name with `.bookmarks` added.
+pub fn bookmarks_path(datafile: &Path) -> PathBuf {
+    let mut name = datafile.as_os_str().to_os_string();
+    name.push(".bookmarks");
+    PathBuf::from(name)
+}
+
+#[derive(Debug, Default)]
+pub struct Bookmarks {
+    names: BTreeMap<String, PathBuf>,
+}
+
+impl Bookmarks {
+    /// Read the bookmarks for conf.datafile; none if there is no file yet.
+    pub fn from_file(conf: &Config) -> Result<Bookmarks, RasdfError> {
+        let path = bookmarks_path(&conf.datafile);
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Bookmarks::default()),
+            Err(e) => return Err(RasdfError::io(&path, e)),
+        };
+
+        let mut bookmarks = Bookmarks::default();
+        for (line, n) in contents.lines().zip(1..) {
+            if line.is_empty() {
+                continue;
+            }
+            let (name, target) = line
+                .split_once('|')
+                .and_then(|(name, target)| {
+                    let name = decode_path(name)?.into_os_string().into_string().ok()?;
+                    Some((name, decode_path(target)?))
+                })
+                .ok_or_else(|| {
+                    RasdfError::parse(&path, format!("can't parse bookmark: {}", line)).at_line(n)
+                })?;
+            bookmarks.names.insert(name, target);
+        }
+        Ok(bookmarks)
+    }
+
+    /// Write the bookmarks out beside conf.datafile. The datafile lock
+    /// must be held.
+    pub fn write_out(&self, conf: &Config) -> Result<(), RasdfError> {
+        let path = bookmarks_path(&conf.datafile);
+        self.write_rows(&path).map_err(|e| RasdfError::io(&path, e))
+    }
+
+    fn write_rows(&self, path: &Path) -> io::Result<()> {
+        let (temp, file) = create_temp_file(path)?;
+        let mut buffer = io::BufWriter::new(file);
+        let written = self
+            .names
+            .iter()
+            .try_for_each(|(name, target)| {
+                writeln!(buffer, "{}|{}", encode_path(Path::new(name)), encode_path(target))
+            })
+            .and_then(|_| buffer.into_inner().map_err(|e| e.into_error()))
+            .and_then(|file| file.sync_all())
+            .and_then(|_| fs::rename(&temp, path));
+        if written.is_err() {
+            let _ = fs::remove_file(&temp);
+            return written;
+        }
+        sync_parent_dir(path)
+    }
+
+    /// The path for a bookmark name.
+    pub fn get(&self, name: &str) -> Option<&Path> {
+        self.names.get(name).map(PathBuf::as_path)
+    }
+
+    /// Set name to mean path, which must exist; returns the path it
+    /// meant before, if any.
+    pub fn set<P: AsRef<Path>>(&mut self, name: &str, path: P) -> io::Result<Option<PathBuf>> {
+        let path = crate::canonicalize(path)?;
+        Ok(self.names.insert(name.to_string(), path))
+    }
+
+    /// Forget a bookmark; false if there was no such name.
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.names.remove(name).is_some()
+    }
+
+    /// Every bookmark, in order of name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Path)> {
+        self.names
+            .iter()
+            .map(|(name, path)| (name.as_str(), path.as_path()))
+    }
+
+    /// The path for a find with a single term that is exactly the name
+    /// of a bookmark, if that path is still there.
+    pub fn find(&self, conf: &Config) -> Option<&Path> {
+        match conf.arguments.as_slice() {
+            [term] => self.get(term.to_str()?).filter(|path| path.exists()),
+            _ => None,
+        }
+    }
+}
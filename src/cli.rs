@@ -0,0 +1,303 @@
+//! Read the command line: the command, its options and its arguments.
+//!
+//! This runs at every prompt, so it is a plain loop over the arguments
+//! rather than anything cleverer.
+
+use crate::config::{Config, Source};
+use crate::config_file::Value;
+use crate::encoding::{path_bytes, path_from_bytes};
+
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+
+/// A command, with the help text for it.
+pub struct Command {
+    pub name: &'static str,
+    pub args: &'static str,
+    pub about: &'static str,
+    /// false if options stop at the first argument, as for add, which
+    /// is given the words of a whole command line to record
+    pub options_anywhere: bool,
+}
+
+pub const COMMANDS: [Command; 15] = [
+    Command {
+        name: "init",
+        args: "",
+        about: "Create a new empty database.",
+        options_anywhere: true,
+    },
+    Command {
+        name: "clean",
+        args: "",
+        about: "Age the ratings and drop the least used rows if there are more than\n\
+                maxlines, and merge the journal into the data file.",
+        options_anywhere: true,
+    },
+    Command {
+        name: "add",
+        args: "path [path...]",
+        about: "Record a visit to each path that exists. Options are only read before\n\
+                the first path.",
+        options_anywhere: false,
+    },
+    Command {
+        name: "remove",
+        args: "path",
+        about: "Remove the row for path from the database.",
+        options_anywhere: false,
+    },
+    Command {
+        name: "find",
+        args: "term [term...]",
+        about: "Print the best scoring path that matches all the terms, in order.",
+        options_anywhere: true,
+    },
+    Command {
+        name: "find-all",
+        args: "term [term...]",
+        about: "Print the score and path of every path that matches all the terms,\n\
+                best last.",
+        options_anywhere: true,
+    },
+    Command {
+        name: "flag",
+        args: "add|remove path flags",
+        about: "Set or clear flags on the row for path: p (pinned), h (hidden),\n\
+                r (removable), or any other letter or digit as a tag of your own.",
+        options_anywhere: true,
+    },
+    Command {
+        name: "history",
+        args: "path",
+        about: "Print the times of the recent visits to path, oldest first.",
+        options_anywhere: true,
+    },
+    Command {
+        name: "bookmark",
+        args: "name path",
+        about: "Make name find path: a find for just the name prints it, whatever\n\
+                the scores.",
+        options_anywhere: true,
+    },
+    Command {
+        name: "unbookmark",
+        args: "name",
+        about: "Forget the bookmark called name.",
+        options_anywhere: true,
+    },
+    Command {
+        name: "bookmarks",
+        args: "",
+        about: "Print each bookmark name and the path it finds.",
+        options_anywhere: true,
+    },
+    Command {
+        name: "convert",
+        args: "text|binary",
+        about: "Rewrite the data file in the given format.",
+        options_anywhere: true,
+    },
+    Command {
+        name: "config",
+        args: "",
+        about: "Print the settings in use and where each came from.",
+        options_anywhere: true,
+    },
+    Command {
+        name: "help",
+        args: "[command]",
+        about: "Print help for all commands, or for one.",
+        options_anywhere: true,
+    },
+    Command {
+        name: "version",
+        args: "",
+        about: "Print the version and the data file in use.",
+        options_anywhere: true,
+    },
+];
+
+const OPTIONS: &str = "
+Options:
+    -a, --all                find files and directories
+    -d, --dirs               find directories only
+    -f, --files              find files only
+    -D, -F, -R               score by date, frecency or rating
+    --method=METHOD          score by date, frecency, rating, visits or recent
+    -s, --strict             the last term must match the last segment
+    -l, --lax                the last term may match anywhere
+    -g, --segments           each term must be in a later segment than the last
+    -G, --any-segments       terms may share a segment
+    --anchor-first           the first term must start a segment
+    -b, --basename           the last term must be the whole last segment
+    -u, --ancestors          find the folders above this one (or ..term)
+    --from=DIR               start from DIR, not the current folder
+    --under=DIR              find only paths in DIR
+    -p, --project            prefer paths in the project around this folder
+    -P, --no-project         do not prefer the project
+    -z, --fuzzy              match the terms with gaps between their letters
+    --regex, --glob          read the terms as regular expressions or globs
+    --matching=MATCHING      match terms literal, fuzzy, regex or glob
+    --tags=TAGS              find only paths flagged with all of TAGS
+    -c, --case-sensitive     match case exactly
+    -i, --case-insensitive   ignore case
+    -S, --smart-case         ignore case in terms that are all lower case
+    -h, --help               print help for the command
+    --                       treat everything after as arguments
+";
+
+/// Look up a command by name.
+pub fn command(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|cmd| cmd.name == name)
+}
+
+/// Help for every command.
+pub fn usage(executable: &str) -> String {
+    let mut text = format!("{} COMMAND [OPTIONS...] [ARGUMENTS...]\n\nCommands:\n", executable);
+    for cmd in COMMANDS.iter() {
+        text.push_str(format!("    {} {}", cmd.name, cmd.args).trim_end());
+        text.push('\n');
+    }
+    text.push_str(OPTIONS);
+    text
+}
+
+/// Help for one command.
+pub fn command_usage(executable: &str, cmd: &Command) -> String {
+    format!(
+        "{} {} [OPTIONS...] {}\n\n{}\n{}",
+        executable, cmd.name, cmd.args, cmd.about, OPTIONS
+    )
+}
+
+/// Read the command line into conf.
+///
+/// The first argument that is not an option is the command; the rest
+/// are its arguments. `--help` turns the command into `help COMMAND`.
+pub fn parse_args<I: IntoIterator<Item = OsString>>(
+    conf: &mut Config,
+    args: I,
+) -> Result<(), String> {
+    let mut args = args.into_iter();
+    conf.executable = args
+        .next()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut options_done = false;
+    let mut help = false;
+    while let Some(arg) = args.next() {
+        // the folder options keep their value as it is, as a folder
+        // need not be valid UTF-8
+        if let Some((name, dir)) = dir_option(&arg).filter(|_| !options_done) {
+            let dir = match dir {
+                Some(dir) => dir,
+                None => args
+                    .next()
+                    .ok_or_else(|| format!("--{} needs a value", name))?,
+            };
+            conf.set_dir(name, &dir, Source::Cli)?;
+            continue;
+        }
+        let text = match arg.to_str() {
+            Some(text) if !options_done && text.starts_with('-') && text != "-" => text,
+            _ => {
+                // a command or an argument; arguments may be paths,
+                // so need not be valid UTF-8
+                if conf.command.is_empty() {
+                    conf.command = arg.to_string_lossy().into_owned();
+                    if command(&conf.command).is_none() {
+                        return Err(format!("not a valid command <{}>", conf.command));
+                    }
+                } else {
+                    conf.arguments.push(arg);
+                    options_done |= command(&conf.command).is_some_and(|cmd| !cmd.options_anywhere);
+                }
+                continue;
+            }
+        };
+
+        if text == "--" {
+            options_done = true;
+        } else if let Some(long) = text.strip_prefix("--") {
+            let (name, value) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (long, None),
+            };
+            match name {
+                "help" => help = true,
+                "all" => conf.set_cli_flag('a', Source::Cli)?,
+                "dirs" => conf.set_cli_flag('d', Source::Cli)?,
+                "files" => conf.set_cli_flag('f', Source::Cli)?,
+                "strict" => conf.set_cli_flag('s', Source::Cli)?,
+                "lax" => conf.set_cli_flag('l', Source::Cli)?,
+                "segments" => conf.set_cli_flag('g', Source::Cli)?,
+                "any-segments" => conf.set_cli_flag('G', Source::Cli)?,
+                "anchor-first" => conf.set("anchor_first", &Value::Boolean(true), Source::Cli)?,
+                "basename" => conf.set_cli_flag('b', Source::Cli)?,
+                "ancestors" => conf.set_cli_flag('u', Source::Cli)?,
+                "project" => conf.set_cli_flag('p', Source::Cli)?,
+                "no-project" => conf.set_cli_flag('P', Source::Cli)?,
+                "fuzzy" => conf.set_cli_flag('z', Source::Cli)?,
+                "regex" | "glob" => {
+                    conf.set("matching", &Value::String(name.to_string()), Source::Cli)?
+                }
+                "case-sensitive" => conf.set_cli_flag('c', Source::Cli)?,
+                "case-insensitive" => conf.set_cli_flag('i', Source::Cli)?,
+                "smart-case" => conf.set_cli_flag('S', Source::Cli)?,
+                // settings that take a value, of the same name
+                "method" | "matching" | "tags" => {
+                    let value = match value {
+                        Some(value) => value,
+                        None => args
+                            .next()
+                            .map(|s| s.to_string_lossy().into_owned())
+                            .ok_or_else(|| format!("--{} needs a value", name))?,
+                    };
+                    conf.set(name, &Value::String(value), Source::Cli)?;
+                    continue;
+                }
+                _ => return Err(format!("unrecognised option --{}", name)),
+            }
+            if value.is_some() {
+                return Err(format!("--{} does not take a value", name));
+            }
+        } else {
+            for cli_flag in text.chars().skip(1) {
+                match cli_flag {
+                    'h' => help = true,
+                    _ => conf.set_cli_flag(cli_flag, Source::Cli)?,
+                }
+            }
+        }
+    }
+
+    if help {
+        let command = std::mem::replace(&mut conf.command, String::from("help"));
+        conf.arguments = if command.is_empty() || command == "help" {
+            vec![]
+        } else {
+            vec![OsString::from(command)]
+        };
+    }
+    Ok(())
+}
+
+/// The name of a folder option, `--from` or `--under`, with its value if
+/// it is given as `--from=DIR`.
+fn dir_option(arg: &OsStr) -> Option<(&'static str, Option<OsString>)> {
+    let bytes = path_bytes(Path::new(arg));
+    let rest = bytes.strip_prefix(b"--")?;
+    for name in ["from", "under"].iter().copied() {
+        match rest.strip_prefix(name.as_bytes()) {
+            Some([]) => return Some((name, None)),
+            Some([b'=', dir @ ..]) => {
+                let dir = path_from_bytes(dir.to_vec())?;
+                return Some((name, Some(dir.into_os_string())));
+            }
+            _ => {}
+        }
+    }
+    None
+}
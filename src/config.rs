@@ -1,80 +1,389 @@
-// Setup config object to read env variables
-// and some local defaults.
+// Setup config object to read the settings file, env variables
+// and command line, and some local defaults.
 
+use crate::cli;
+use crate::config_file::{self, Value};
+use crate::encoding::path_bytes;
+use crate::glob::glob_match;
+
+use std::collections::HashMap;
 use std::env;
-use std::path::PathBuf;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 
 const VERSION: &str = "0.1.2";
 
-#[derive(Copy, Clone, Debug)]
+/// Settings that can be given in the settings file, in the order that
+/// `rasdf config` shows them.
+const SETTINGS: [&str; 34] = [
+    "datafile",
+    "backend",
+    "method",
+    "growth",
+    "rating_cap",
+    "history",
+    "recent_days",
+    "maxlines",
+    "age_half_life",
+    "drop_below",
+    "missing_grace",
+    "journal_max",
+    "logfile",
+    "lock_timeout",
+    "types",
+    "strict",
+    "segments",
+    "anchor_first",
+    "basename",
+    "ancestors",
+    "from",
+    "under",
+    "project",
+    "project_markers",
+    "matching",
+    "case",
+    "tags",
+    "blacklist",
+    "ignore",
+    "removable",
+    "decay",
+    "buckets",
+    "weights",
+    "half_life",
+];
+
+/// Environment variables, and the setting each one overrides.
+const ENV_VARS: [(&str, &str); 9] = [
+    ("RASDF_DATAFILE", "datafile"),
+    ("RASDF_BACKEND", "backend"),
+    ("RASDF_METHOD", "method"),
+    ("RASDF_DECAY", "decay"),
+    ("RASDF_MAXLINES", "maxlines"),
+    ("RASDF_JOURNAL_MAX", "journal_max"),
+    ("RASDF_LOGFILE", "logfile"),
+    ("RASDF_LOCK_TIMEOUT", "lock_timeout"),
+    ("RASDF_FLAGS", "flags"),
+];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum ScoreMethod {
     Date,
     Rating,
     Frecency,
+    /// frecency summed over each recorded visit
+    Visits,
+    /// number of visits in the last recent_days
+    Recent,
 }
 
 impl ScoreMethod {
-    fn from(s: &str) -> Self {
+    fn from(s: &str) -> Option<Self> {
+        match s {
+            "date" => Some(ScoreMethod::Date),
+            "rating" => Some(ScoreMethod::Rating),
+            "frecency" => Some(ScoreMethod::Frecency),
+            "visits" => Some(ScoreMethod::Visits),
+            "recent" => Some(ScoreMethod::Recent),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ScoreMethod::Date => "date",
+            ScoreMethod::Rating => "rating",
+            ScoreMethod::Frecency => "frecency",
+            ScoreMethod::Visits => "visits",
+            ScoreMethod::Recent => "recent",
+        }
+    }
+}
+
+/// Format of the datafile
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Backend {
+    Text,
+    Binary,
+}
+
+impl Backend {
+    pub fn from(s: &str) -> Option<Self> {
+        match s {
+            "text" => Some(Backend::Text),
+            "binary" => Some(Backend::Binary),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Backend::Text => "text",
+            Backend::Binary => "binary",
+        }
+    }
+}
+
+/// How frecency falls off with the age of the last visit; see decay.rs
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DecayModel {
+    Step,
+    Exponential,
+    Fasd,
+    Zoxide,
+}
+
+impl DecayModel {
+    fn from(s: &str) -> Option<Self> {
         match s {
-            "date" => ScoreMethod::Date,
-            "rating" => ScoreMethod::Rating,
-            "frecency" => ScoreMethod::Frecency,
-            _ => ScoreMethod::Frecency,
+            "step" => Some(DecayModel::Step),
+            "exponential" => Some(DecayModel::Exponential),
+            "fasd" => Some(DecayModel::Fasd),
+            "zoxide" => Some(DecayModel::Zoxide),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            DecayModel::Step => "step",
+            DecayModel::Exponential => "exponential",
+            DecayModel::Fasd => "fasd",
+            DecayModel::Zoxide => "zoxide",
+        }
+    }
+}
+
+/// How much each visit adds to a rating; see growth.rs
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Growth {
+    Reciprocal,
+    Linear,
+    Log,
+    Capped,
+}
+
+impl Growth {
+    fn from(s: &str) -> Option<Self> {
+        match s {
+            "reciprocal" => Some(Growth::Reciprocal),
+            "linear" => Some(Growth::Linear),
+            "log" => Some(Growth::Log),
+            "capped" => Some(Growth::Capped),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Growth::Reciprocal => "reciprocal",
+            Growth::Linear => "linear",
+            Growth::Log => "log",
+            Growth::Capped => "capped",
+        }
+    }
+}
+
+/// How find matches the terms against a path
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Matching {
+    /// each term as it is, in order
+    Literal,
+    /// the bytes of the terms in order, with gaps; see fuzzy.rs
+    Fuzzy,
+    /// each term a regular expression
+    Regex,
+    /// each term a glob pattern for whole segments
+    Glob,
+}
+
+impl Matching {
+    fn from(s: &str) -> Option<Self> {
+        match s {
+            "literal" => Some(Matching::Literal),
+            "fuzzy" => Some(Matching::Fuzzy),
+            "regex" => Some(Matching::Regex),
+            "glob" => Some(Matching::Glob),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Matching::Literal => "literal",
+            Matching::Fuzzy => "fuzzy",
+            Matching::Regex => "regex",
+            Matching::Glob => "glob",
         }
     }
 }
 
-pub struct Config<'a> {
+/// Whether find tells upper from lower case
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Case {
+    Sensitive,
+    Insensitive,
+    /// insensitive, except for a term with an upper-case letter in it
+    Smart,
+}
+
+impl Case {
+    fn from(s: &str) -> Option<Self> {
+        match s {
+            "sensitive" => Some(Case::Sensitive),
+            "insensitive" => Some(Case::Insensitive),
+            "smart" => Some(Case::Smart),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Case::Sensitive => "sensitive",
+            Case::Insensitive => "insensitive",
+            Case::Smart => "smart",
+        }
+    }
+
+    /// true if term is to be matched with its case as it is
+    pub fn is_sensitive(&self, term: &str) -> bool {
+        match self {
+            Case::Sensitive => true,
+            Case::Insensitive => false,
+            Case::Smart => term.chars().any(char::is_uppercase),
+        }
+    }
+}
+
+/// Where the value of a setting came from
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    Env(&'static str),
+    Cli,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "file {}", path.display()),
+            Source::Env(var) => write!(f, "env {}", var),
+            Source::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+pub struct Config {
     pub version: String,
     pub executable: String,
     pub command: String,
     pub method: ScoreMethod,
+    pub growth: Growth,
+    pub rating_cap: f32,
+    pub history: usize,
+    pub recent_days: u64,
     pub datafile: PathBuf,
-    pub tempfile: PathBuf,
+    pub backend: Backend,
     pub maxlines: usize,
+    pub age_half_life: u64,
+    pub drop_below: f32,
+    pub missing_grace: u64,
+    pub journal_max: u64,
     pub logging: Option<PathBuf>,
+    pub lock_timeout: u64,
     pub current_time: u64,
     pub find_dirs: bool,
     pub find_files: bool,
     pub strict: bool,
-    pub case_sensitive: bool,
-    pub flags: String,
-    pub cmd_blacklist: Vec<&'a str>,
-    pub arguments: Vec<String>,
+    /// each term in a later segment than the one before
+    pub segments: bool,
+    /// the first term at the start of a segment
+    pub anchor_first: bool,
+    /// the last term the whole of the last segment
+    pub basename: bool,
+    /// look among the folders above from, not in the database
+    pub ancestors: bool,
+    /// the folder to search above, if not the current one
+    pub from: Option<PathBuf>,
+    /// the folder results have to be in
+    pub under: Option<PathBuf>,
+    /// put paths in the project around from first
+    pub project: bool,
+    /// names of files or folders that mark the root of a project
+    pub project_markers: Vec<String>,
+    pub matching: Matching,
+    pub case: Case,
+    /// tags a row must all have to be found
+    pub tags: String,
+    pub cmd_blacklist: Vec<String>,
+    pub ignore: Vec<String>,
+    pub removable: Vec<String>,
+    pub decay: DecayModel,
+    pub buckets: Vec<u64>,
+    pub weights: Vec<f32>,
+    pub half_life: u64,
+    pub arguments: Vec<OsString>,
+    pub sources: HashMap<&'static str, Source>,
+    pub warnings: Vec<String>,
 }
 
-impl Default for Config<'_> {
+impl Default for Config {
     fn default() -> Self {
-        Self::new()
+        Self::from_env()
     }
 }
 
-impl Config<'_> {
-    pub fn new() -> Config<'static> {
-        let mut config = Config {
+impl Config {
+    /// Settings from the settings file, overridden by environment
+    /// variables, overridden by the command line; Err if the command
+    /// line can't be understood.
+    pub fn new() -> Result<Config, String> {
+        let mut config = Config::from_env();
+        cli::parse_args(&mut config, env::args_os())?;
+
+        // return the config
+        Ok(config)
+    }
+
+    /// Settings from the settings file, overridden by environment
+    /// variables, with no command or arguments.
+    pub fn from_env() -> Config {
+        let mut config = Config::defaults();
+
+        if let Some(path) = config_path() {
+            config.read_file(&path);
+        }
+        config.read_env();
+        config.check_buckets();
+        config
+    }
+
+    /// The built-in settings, before the settings file, environment
+    /// or command line have their say.
+    pub fn defaults() -> Config {
+        Config {
             version: String::from(VERSION),
             executable: String::new(),
             command: String::new(),
-            method: if let Ok(s) = env::var("RASDF_METHOD") {
-                ScoreMethod::from(&s)
-            } else {
-                ScoreMethod::Frecency
-            },
-            datafile: if let Ok(filepath) = env::var("RASDF_DATAFILE") {
-                PathBuf::from(filepath)
-            } else {
-                PathBuf::from("/home/tim/.config/rasdf/rasdf.dat")
-            },
-            tempfile: PathBuf::from("/tmp/rasdf_tf.dat"),
-            maxlines: match env::var("RASDF_MAXLINES").map(|var| var.parse::<usize>()) {
-                Ok(Ok(maxlines)) => maxlines,
-                _ => 200,
-            },
-            logging: match env::var("RASDF_LOGFILE") {
-                Ok(filepath) => Some(PathBuf::from(filepath)),
-                _ => None,
-            },
+            method: ScoreMethod::Frecency,
+            growth: Growth::Reciprocal,
+            rating_cap: 100.0,
+            history: 10,
+            recent_days: 30,
+            datafile: default_datafile(),
+            backend: Backend::Text,
+            maxlines: 200,
+            age_half_life: 30 * 86400,
+            drop_below: 0.1,
+            missing_grace: 7 * 86400,
+            journal_max: 65536,
+            logging: None,
+            lock_timeout: 2000,
             current_time: SystemTime::now()
                 .duration_since(SystemTime::UNIX_EPOCH)
                 .unwrap() // okay because current time !< epoch
@@ -82,77 +391,533 @@ impl Config<'_> {
             find_dirs: true,
             find_files: false,
             strict: true,
-            case_sensitive: true,
-            flags: String::new(),
+            segments: false,
+            anchor_first: false,
+            basename: false,
+            ancestors: false,
+            from: None,
+            under: None,
+            project: false,
+            project_markers: [
+                ".git",
+                ".hg",
+                ".svn",
+                "Cargo.toml",
+                "package.json",
+                "go.mod",
+                "pyproject.toml",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+            matching: Matching::Literal,
+            case: Case::Sensitive,
+            tags: String::new(),
             cmd_blacklist: [
                 "rasdf", "ls", "dir", "vdir", "ddir", "cd", "rm", "rmdir", "tree",
-            ].to_vec(),
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+            ignore: Vec::new(),
+            removable: ["/media/**", "/mnt/**", "/run/media/**", "/net/**"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            decay: DecayModel::Step,
+            buckets: vec![3600, 86400, 604800],
+            weights: vec![6.0, 4.0, 2.0, 1.0],
+            half_life: 604800,
             arguments: vec![],
+            sources: HashMap::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    fn read_file(&mut self, path: &Path) {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+            Err(e) => {
+                self.warnings.push(format!("Can't read {}: {}", path.display(), e));
+                return;
+            }
         };
 
-        // override from $RASDF_FLAGS
-        if let Ok(cli_flags) = env::var("RASDF_FLAGS") {
-            for cli_flag in cli_flags.chars() {
-                config.set_cli_flag(cli_flag);
+        match config_file::parse(&text) {
+            Ok(pairs) => {
+                for (key, value) in pairs {
+                    if let Err(e) = self.set(&key, &value, Source::File(path.to_path_buf())) {
+                        self.warnings.push(format!("{}: {}", path.display(), e));
+                    }
+                }
             }
+            Err(e) => self.warnings.push(format!("{}: {}", path.display(), e)),
         }
+    }
 
-        // overriding by command line flags...
-        let mut argiter = env::args().peekable();
-
-        // first arguments are the executable and the command
-        config.executable = argiter.next().unwrap_or_default();
-        config.command = argiter.next().unwrap_or_default();
+    /// The step decay needs a weight for each bucket, and one more for
+    /// everything older; go back to the defaults if they don't agree.
+    fn check_buckets(&mut self) {
+        if self.weights.len() != self.buckets.len() + 1 {
+            self.warnings.push(format!(
+                "{} weights don't fit {} buckets; using the default step decay",
+                self.weights.len(),
+                self.buckets.len()
+            ));
+            let defaults = Config::defaults();
+            self.buckets = defaults.buckets;
+            self.weights = defaults.weights;
+            self.sources.remove("buckets");
+            self.sources.remove("weights");
+        }
+    }
 
-        while let Some(arg) = argiter.peek() {
-            if !arg.starts_with('-') {
-                break;
-            }
-            for cli_flag in arg.chars().skip(1) {
-                config.set_cli_flag(cli_flag);
+    fn read_env(&mut self) {
+        for (var, key) in ENV_VARS.iter() {
+            if let Ok(s) = env::var(var) {
+                if let Err(e) = self.set(key, &Value::String(s), Source::Env(var)) {
+                    self.warnings.push(format!("${}: {}", var, e));
+                }
             }
+        }
+    }
 
-            argiter.next();
+    /// Set one setting by name, from a value in the settings file or
+    /// the text of an environment variable.
+    pub fn set(&mut self, key: &str, value: &Value, source: Source) -> Result<(), String> {
+        // the flags are a shorthand for several settings at once
+        if key == "flags" {
+            for cli_flag in string(value)?.chars() {
+                self.set_cli_flag(cli_flag, source.clone())?;
+            }
+            return Ok(());
         }
 
-        // remainder are collected into arguments
-        config.arguments = argiter.collect();
+        let Some(name) = SETTINGS.iter().find(|&&name| name == key) else {
+            return Err(format!("unknown setting '{}'", key));
+        };
+        match *name {
+            "datafile" => self.datafile = PathBuf::from(expand_home(string(value)?)),
+            "backend" => {
+                self.backend = Backend::from(string(value)?)
+                    .ok_or_else(|| format!("backend must be text or binary, not {}", value))?
+            }
+            "method" => {
+                self.method = ScoreMethod::from(string(value)?).ok_or_else(|| {
+                    format!(
+                        "method must be date, rating, frecency, visits or recent, not {}",
+                        value
+                    )
+                })?
+            }
+            "growth" => {
+                self.growth = Growth::from(string(value)?).ok_or_else(|| {
+                    format!("growth must be reciprocal, linear, log or capped, not {}", value)
+                })?
+            }
+            "rating_cap" => match float(value)? {
+                cap if cap > 0.0 => self.rating_cap = cap,
+                _ => return Err(format!("rating_cap must be more than 0, not {}", value)),
+            },
+            "history" => self.history = number(value)? as usize,
+            "recent_days" => self.recent_days = number(value)?,
+            "maxlines" => self.maxlines = number(value)? as usize,
+            "age_half_life" => match number(value)? {
+                0 => return Err(String::from("age_half_life must be more than 0 seconds")),
+                half_life => self.age_half_life = half_life,
+            },
+            "drop_below" => self.drop_below = float(value)?,
+            "missing_grace" => self.missing_grace = number(value)?,
+            "journal_max" => self.journal_max = number(value)?,
+            "logfile" => self.logging = Some(PathBuf::from(expand_home(string(value)?))),
+            "lock_timeout" => self.lock_timeout = number(value)?,
+            "types" => match string(value)? {
+                "all" => self.set_cli_flag('a', source.clone())?,
+                "dirs" => self.set_cli_flag('d', source.clone())?,
+                "files" => self.set_cli_flag('f', source.clone())?,
+                _ => return Err(format!("types must be all, dirs or files, not {}", value)),
+            },
+            "strict" => self.strict = boolean(value)?,
+            "segments" => self.segments = boolean(value)?,
+            "anchor_first" => self.anchor_first = boolean(value)?,
+            "basename" => self.basename = boolean(value)?,
+            "ancestors" => self.ancestors = boolean(value)?,
+            "from" => self.from = Some(expand_home_path(Path::new(string(value)?))),
+            "under" => self.under = Some(expand_home_path(Path::new(string(value)?))),
+            "project" => self.project = boolean(value)?,
+            "project_markers" => self.project_markers = strings(value)?,
+            "matching" => {
+                self.matching = Matching::from(string(value)?).ok_or_else(|| {
+                    format!("matching must be literal, fuzzy, regex or glob, not {}", value)
+                })?
+            }
+            "case" => {
+                self.case = Case::from(string(value)?).ok_or_else(|| {
+                    format!("case must be sensitive, insensitive or smart, not {}", value)
+                })?
+            }
+            "tags" => {
+                let tags = string(value)?;
+                if !tags.chars().all(crate::is_flag) {
+                    return Err(format!("tags must be letters or digits, not {}", value));
+                }
+                self.tags = tags.to_string();
+            }
+            "blacklist" => self.cmd_blacklist = strings(value)?,
+            "ignore" => self.ignore = strings(value)?.iter().map(|s| expand_home(s)).collect(),
+            "removable" => {
+                self.removable = strings(value)?.iter().map(|s| expand_home(s)).collect()
+            }
+            "decay" => {
+                self.decay = DecayModel::from(string(value)?).ok_or_else(|| {
+                    format!("decay must be step, exponential, fasd or zoxide, not {}", value)
+                })?
+            }
+            "buckets" => {
+                let buckets = numbers(value)?;
+                if buckets.windows(2).any(|pair| pair[0] >= pair[1]) {
+                    return Err(String::from("buckets must be in increasing order"));
+                }
+                self.buckets = buckets;
+            }
+            "weights" => {
+                let weights = floats(value)?;
+                if weights.is_empty() || weights.iter().any(|&w| w < 0.0) {
+                    return Err(String::from("weights must be one or more numbers, none negative"));
+                }
+                self.weights = weights;
+            }
+            "half_life" => match number(value)? {
+                0 => return Err(String::from("half_life must be more than 0 seconds")),
+                half_life => self.half_life = half_life,
+            },
+            _ => unreachable!(),
+        }
+        self.sources.insert(name, source);
+        Ok(())
+    }
 
-        // return the config
-        config
+    /// Set from or under to a folder as it was given, which need not be
+    /// valid UTF-8 as the value of set has to be.
+    pub fn set_dir(&mut self, key: &str, dir: &OsStr, source: Source) -> Result<(), String> {
+        let dir = expand_home_path(Path::new(dir));
+        let name = match key {
+            "from" => {
+                self.from = Some(dir);
+                "from"
+            }
+            "under" => {
+                self.under = Some(dir);
+                "under"
+            }
+            _ => return Err(format!("{} is not a folder setting", key)),
+        };
+        self.sources.insert(name, source);
+        Ok(())
     }
 
-    fn set_cli_flag(&mut self, cli_flag: char) {
-        match cli_flag {
+    /// Apply one of the single-letter flags, as on the command line.
+    pub(crate) fn set_cli_flag(&mut self, cli_flag: char, source: Source) -> Result<(), String> {
+        let name = match cli_flag {
             'a' => {
                 self.find_dirs = true;
                 self.find_files = true;
+                "types"
             }
             'd' => {
                 self.find_dirs = true;
                 self.find_files = false;
+                "types"
             }
             'f' => {
                 self.find_dirs = false;
                 self.find_files = true;
+                "types"
+            }
+            'D' => {
+                self.method = ScoreMethod::Date;
+                "method"
+            }
+            'F' => {
+                self.method = ScoreMethod::Frecency;
+                "method"
+            }
+            'R' => {
+                self.method = ScoreMethod::Rating;
+                "method"
+            }
+            's' => {
+                self.strict = true;
+                "strict"
+            }
+            'l' => {
+                self.strict = false;
+                "strict"
+            }
+            'g' => {
+                self.segments = true;
+                "segments"
+            }
+            'G' => {
+                self.segments = false;
+                "segments"
+            }
+            'b' => {
+                self.basename = true;
+                "basename"
+            }
+            'u' => {
+                self.ancestors = true;
+                "ancestors"
+            }
+            'p' => {
+                self.project = true;
+                "project"
+            }
+            'P' => {
+                self.project = false;
+                "project"
+            }
+            'z' => {
+                self.matching = Matching::Fuzzy;
+                "matching"
+            }
+            'c' => {
+                self.case = Case::Sensitive;
+                "case"
+            }
+            'i' => {
+                self.case = Case::Insensitive;
+                "case"
+            }
+            'S' => {
+                self.case = Case::Smart;
+                "case"
             }
-            'D' => self.method = ScoreMethod::Date,
-            'F' => self.method = ScoreMethod::Frecency,
-            'R' => self.method = ScoreMethod::Rating,
-            's' => self.strict = true,
-            'l' => self.strict = false,
-            'c' => self.case_sensitive = true,
-            'i' => self.case_sensitive = false,
 
-            _ => panic!("Unrecognised option {}", cli_flag),
+            _ => return Err(format!("unrecognised option -{}", cli_flag)),
         };
+        self.sources.insert(name, source);
+        Ok(())
+    }
+
+    /// The current value of a setting, as it would be written in the
+    /// settings file.
+    pub fn value(&self, key: &str) -> Option<Value> {
+        let path = |p: &Path| Value::String(p.to_string_lossy().into_owned());
+        let strings = |v: &[String]| Value::Array(v.iter().cloned().map(Value::String).collect());
+        Some(match key {
+            "datafile" => path(&self.datafile),
+            "backend" => Value::String(self.backend.name().to_string()),
+            "method" => Value::String(self.method.name().to_string()),
+            "growth" => Value::String(self.growth.name().to_string()),
+            "rating_cap" => Value::Float(self.rating_cap as f64),
+            "history" => Value::Integer(self.history as i64),
+            "recent_days" => Value::Integer(self.recent_days as i64),
+            "maxlines" => Value::Integer(self.maxlines as i64),
+            "age_half_life" => Value::Integer(self.age_half_life as i64),
+            "drop_below" => Value::Float(self.drop_below as f64),
+            "missing_grace" => Value::Integer(self.missing_grace as i64),
+            "journal_max" => Value::Integer(self.journal_max as i64),
+            "logfile" => self.logging.as_deref().map(path)?,
+            "lock_timeout" => Value::Integer(self.lock_timeout as i64),
+            "types" => Value::String(
+                match (self.find_dirs, self.find_files) {
+                    (true, true) => "all",
+                    (false, true) => "files",
+                    _ => "dirs",
+                }
+                .to_string(),
+            ),
+            "strict" => Value::Boolean(self.strict),
+            "segments" => Value::Boolean(self.segments),
+            "anchor_first" => Value::Boolean(self.anchor_first),
+            "basename" => Value::Boolean(self.basename),
+            "ancestors" => Value::Boolean(self.ancestors),
+            "from" => self.from.as_deref().map(path)?,
+            "under" => self.under.as_deref().map(path)?,
+            "project" => Value::Boolean(self.project),
+            "project_markers" => strings(&self.project_markers),
+            "matching" => Value::String(self.matching.name().to_string()),
+            "case" => Value::String(self.case.name().to_string()),
+            "tags" => Value::String(self.tags.clone()),
+            "blacklist" => strings(&self.cmd_blacklist),
+            "ignore" => strings(&self.ignore),
+            "removable" => strings(&self.removable),
+            "decay" => Value::String(self.decay.name().to_string()),
+            "buckets" => Value::Array(
+                self.buckets
+                    .iter()
+                    .map(|&b| Value::Integer(b as i64))
+                    .collect(),
+            ),
+            "weights" => Value::Array(
+                self.weights
+                    .iter()
+                    .map(|&w| Value::Float(w as f64))
+                    .collect(),
+            ),
+            "half_life" => Value::Integer(self.half_life as i64),
+            _ => return None,
+        })
+    }
+
+    /// Every setting with its value and where it came from, as lines of
+    /// a settings file.
+    pub fn describe(&self) -> String {
+        let mut lines = String::new();
+        for name in SETTINGS.iter() {
+            let source = self.sources.get(name).unwrap_or(&Source::Default);
+            match self.value(name) {
+                Some(value) => lines.push_str(&format!("{} = {}  # {}\n", name, value, source)),
+                None => lines.push_str(&format!("# {} is not set\n", name)),
+            }
+        }
+        lines
+    }
+
+    /// true if path matches one of the ignore patterns
+    pub fn ignores(&self, path: &Path) -> bool {
+        matches_any(&self.ignore, path)
+    }
+
+    /// true if path matches one of the patterns for removable or
+    /// network mounts
+    pub fn is_removable(&self, path: &Path) -> bool {
+        matches_any(&self.removable, path)
     }
 }
 
+fn matches_any(patterns: &[String], path: &Path) -> bool {
+    let path = path_bytes(path);
+    patterns
+        .iter()
+        .any(|pattern| glob_match(pattern.as_bytes(), &path))
+}
+
+fn string(value: &Value) -> Result<&str, String> {
+    value
+        .as_str()
+        .ok_or_else(|| format!("expected a string, not {}", value))
+}
+
+fn number(value: &Value) -> Result<u64, String> {
+    match value {
+        Value::Integer(i) if *i >= 0 => Ok(*i as u64),
+        Value::String(s) => s
+            .parse()
+            .map_err(|_| format!("expected a whole number, not {}", value)),
+        _ => Err(format!("expected a whole number, not {}", value)),
+    }
+}
+
+fn boolean(value: &Value) -> Result<bool, String> {
+    match value {
+        Value::Boolean(b) => Ok(*b),
+        Value::String(s) if s == "true" => Ok(true),
+        Value::String(s) if s == "false" => Ok(false),
+        _ => Err(format!("expected true or false, not {}", value)),
+    }
+}
+
+fn strings(value: &Value) -> Result<Vec<String>, String> {
+    match value {
+        Value::Array(items) => items.iter().map(|v| string(v).map(String::from)).collect(),
+        _ => Err(format!("expected a list of strings, not {}", value)),
+    }
+}
+
+fn numbers(value: &Value) -> Result<Vec<u64>, String> {
+    match value {
+        Value::Array(items) => items.iter().map(number).collect(),
+        _ => Err(format!("expected a list of whole numbers, not {}", value)),
+    }
+}
+
+fn float(value: &Value) -> Result<f32, String> {
+    match value {
+        Value::String(s) => s.parse().ok(),
+        _ => value.as_float().map(|f| f as f32),
+    }
+    .ok_or_else(|| format!("expected a number, not {}", value))
+}
+
+fn floats(value: &Value) -> Result<Vec<f32>, String> {
+    match value {
+        Value::Array(items) => items
+            .iter()
+            .map(|v| {
+                v.as_float()
+                    .map(|f| f as f32)
+                    .ok_or_else(|| format!("expected a number, not {}", v))
+            })
+            .collect(),
+        _ => Err(format!("expected a list of numbers, not {}", value)),
+    }
+}
+
+/// Replace a leading `~/` with the user's home directory.
+fn expand_home(s: &str) -> String {
+    match (s.strip_prefix("~/"), home_dir()) {
+        (Some(rest), Some(home)) => format!("{}/{}", home, rest),
+        _ => s.to_string(),
+    }
+}
+
+/// As expand_home, for a path that need not be valid UTF-8.
+fn expand_home_path(path: &Path) -> PathBuf {
+    match (path_bytes(path).starts_with(b"~/"), home_dir()) {
+        (true, Some(home)) => PathBuf::from(home).join(path.strip_prefix("~").unwrap_or(path)),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// The datafile if none is set: rasdf/rasdf.dat in $XDG_DATA_HOME or
+/// else $HOME/.local/share.
+fn default_datafile() -> PathBuf {
+    datafile_in(env::var_os("XDG_DATA_HOME"), home_dir())
+}
+
+fn datafile_in(data_home: Option<OsString>, home: Option<String>) -> PathBuf {
+    let Some(home) = home.map(PathBuf::from) else {
+        return match data_home {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("rasdf").join("rasdf.dat"),
+            _ => PathBuf::from("rasdf.dat"),
+        };
+    };
+    let datafile = match data_home {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => home.join(".local").join("share"),
+    }
+    .join("rasdf")
+    .join("rasdf.dat");
+
+    // carry on with a datafile in the old place until it's moved
+    let legacy = home.join(".config").join("rasdf").join("rasdf.dat");
+    if !datafile.exists() && legacy.exists() {
+        return legacy;
+    }
+    datafile
+}
+
+/// The settings file: $RASDF_CONFIG, or rasdf/config.toml in
+/// $XDG_CONFIG_HOME or else $HOME/.config.
+pub fn config_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("RASDF_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(home_dir()?).join(".config"),
+    };
+    Some(config_home.join("rasdf").join("config.toml"))
+}
+
 /// Return the whole command line as seen by env::args
 pub fn command_line() -> String {
-    env::args().fold(String::new(), |mut s, arg| {
-        s.push_str(&arg);
+    env::args_os().fold(String::new(), |mut s, arg| {
+        s.push_str(&arg.to_string_lossy());
         s.push(' ');
         s
     })
@@ -162,3 +927,35 @@ pub fn command_line() -> String {
 pub fn home_dir() -> Option<String> {
     env::var("HOME").ok()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_datafile() {
+        let home = env::temp_dir().join(format!("rasdf-data-home-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&home);
+        fs::create_dir_all(&home).unwrap();
+        let home_str = || Some(home.to_string_lossy().into_owned());
+
+        assert_eq!(
+            datafile_in(Some(OsString::from("/xdg/data")), home_str()),
+            PathBuf::from("/xdg/data/rasdf/rasdf.dat")
+        );
+        assert_eq!(
+            datafile_in(None, home_str()),
+            home.join(".local/share/rasdf/rasdf.dat")
+        );
+        assert_eq!(
+            datafile_in(Some(OsString::new()), home_str()),
+            home.join(".local/share/rasdf/rasdf.dat")
+        );
+
+        // an existing datafile in the old place is still used
+        let legacy = home.join(".config/rasdf/rasdf.dat");
+        fs::create_dir_all(legacy.parent().unwrap()).unwrap();
+        fs::write(&legacy, "").unwrap();
+        assert_eq!(datafile_in(None, home_str()), legacy);
+    }
+}
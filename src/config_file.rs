@@ -0,0 +1,167 @@
+//! Read the settings file, `config.toml`.
+//!
+//! The file is parsed by the toml crate; the values a setting can have
+//! are strings, integers, floats, booleans or arrays of those, and keys
+//! inside a table are returned as `table.key`.
+
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Value::Integer(i) => Some(*i as f64),
+            Value::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+}
+
+/// Values as they would be written in the file
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "{:?}", s),
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Array(items) => {
+                write!(f, "[")?;
+                for (n, item) in items.iter().enumerate() {
+                    if n > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+/// Parse the text of a settings file into (key, value) pairs, in order.
+pub fn parse(text: &str) -> Result<Vec<(String, Value)>, String> {
+    let table: toml::Table = text.parse().map_err(|e| describe(text, &e))?;
+    let mut pairs = Vec::new();
+    flatten("", table, &mut pairs)?;
+    Ok(pairs)
+}
+
+/// The message of a toml error, with the line it was found on.
+fn describe(text: &str, e: &toml::de::Error) -> String {
+    match e.span() {
+        Some(span) => format!(
+            "line {}: {}",
+            1 + text[..span.start].matches('\n').count(),
+            e.message()
+        ),
+        None => e.message().to_string(),
+    }
+}
+
+/// Add the pairs in table to pairs, with the keys of nested tables
+/// joined by dots.
+fn flatten(
+    prefix: &str,
+    table: toml::Table,
+    pairs: &mut Vec<(String, Value)>,
+) -> Result<(), String> {
+    for (key, value) in table {
+        let key = match prefix.is_empty() {
+            true => key,
+            false => format!("{}.{}", prefix, key),
+        };
+        match value {
+            toml::Value::Table(table) => flatten(&key, table, pairs)?,
+            value => match convert(value) {
+                Some(value) => pairs.push((key, value)),
+                None => return Err(format!("can't read value for {}", key)),
+            },
+        }
+    }
+    Ok(())
+}
+
+/// The value, if it is one that a setting can have.
+fn convert(value: toml::Value) -> Option<Value> {
+    match value {
+        toml::Value::String(s) => Some(Value::String(s)),
+        toml::Value::Integer(i) => Some(Value::Integer(i)),
+        toml::Value::Float(f) => Some(Value::Float(f)),
+        toml::Value::Boolean(b) => Some(Value::Boolean(b)),
+        toml::Value::Array(items) => items
+            .into_iter()
+            .map(convert)
+            .collect::<Option<_>>()
+            .map(Value::Array),
+        toml::Value::Datetime(_) | toml::Value::Table(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let pairs = parse(
+            "# settings\n\
+             datafile = \"/tmp/x.dat\" # trailing\n\
+             maxlines = 1_000\n\
+             blacklist = [\n  'ls', \"cd\",\n]\n\
+             [weights]\n\
+             hour = 6.5\n\
+             on = true\n",
+        )
+        .unwrap();
+        assert_eq!(
+            pairs,
+            vec![
+                (
+                    String::from("datafile"),
+                    Value::String(String::from("/tmp/x.dat"))
+                ),
+                (String::from("maxlines"), Value::Integer(1000)),
+                (
+                    String::from("blacklist"),
+                    Value::Array(vec![
+                        Value::String(String::from("ls")),
+                        Value::String(String::from("cd"))
+                    ])
+                ),
+                (String::from("weights.hour"), Value::Float(6.5)),
+                (String::from("weights.on"), Value::Boolean(true)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("a = 1\nb = \"open\n")
+            .unwrap_err()
+            .starts_with("line 2: "));
+        assert!(parse("a = 1 2\n").unwrap_err().starts_with("line 1: "));
+        assert!(parse("= 1").is_err());
+        assert!(parse("a = [1, 2").is_err());
+        assert!(parse("a = 1\na = 2\n").is_err());
+        assert_eq!(
+            parse("when = 1979-05-27").unwrap_err(),
+            "can't read value for when"
+        );
+    }
+}
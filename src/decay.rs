@@ -0,0 +1,80 @@
+//! Decay models for frecency.
+//!
+//! The frecency of a path is its rating scaled by a factor for the age
+//! of its last visit. Each model is a different curve for that factor,
+//! chosen by the `decay` setting.
+
+use crate::config::{Config, DecayModel};
+
+pub trait Decay {
+    /// Factor for a last visit age seconds ago.
+    fn factor(&self, conf: &Config, age: u64) -> f32;
+}
+
+/// The curve for a given choice of model.
+pub fn decay(model: DecayModel) -> &'static dyn Decay {
+    match model {
+        DecayModel::Step => &StepDecay,
+        DecayModel::Exponential => &ExponentialDecay,
+        DecayModel::Fasd => &FasdDecay,
+        DecayModel::Zoxide => &ZoxideDecay,
+    }
+}
+
+/// Falls in steps: conf.weights[i] for ages up to conf.buckets[i]
+/// seconds, and the last weight for anything older.
+pub struct StepDecay;
+
+impl Decay for StepDecay {
+    fn factor(&self, conf: &Config, age: u64) -> f32 {
+        let bucket = conf
+            .buckets
+            .iter()
+            .position(|&limit| age <= limit)
+            .unwrap_or(conf.buckets.len());
+        conf.weights
+            .get(bucket)
+            .or_else(|| conf.weights.last())
+            .copied()
+            .unwrap_or(1.0)
+    }
+}
+
+/// Halves every conf.half_life seconds, from 1 for a visit just now.
+pub struct ExponentialDecay;
+
+impl Decay for ExponentialDecay {
+    fn factor(&self, conf: &Config, age: u64) -> f32 {
+        0.5f32.powf(age as f32 / conf.half_life.max(1) as f32)
+    }
+}
+
+/// The fixed curve of fasd: 6 within the hour, 4 within the day, 2
+/// within the week and 1 after that.
+pub struct FasdDecay;
+
+impl Decay for FasdDecay {
+    fn factor(&self, _conf: &Config, age: u64) -> f32 {
+        match age {
+            0..=3599 => 6.0,
+            3600..=86399 => 4.0,
+            86400..=604799 => 2.0,
+            _ => 1.0,
+        }
+    }
+}
+
+/// The fixed curve of zoxide: 4 within the hour, 2 within the day, 1/2
+/// within the week and 1/4 after that.
+pub struct ZoxideDecay;
+
+impl Decay for ZoxideDecay {
+    fn factor(&self, _conf: &Config, age: u64) -> f32 {
+        match age {
+            0..=3599 => 4.0,
+            3600..=86399 => 2.0,
+            86400..=604799 => 0.5,
+            _ => 0.25,
+        }
+    }
+}
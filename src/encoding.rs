@@ -0,0 +1,98 @@
+//! Encoding of paths in the datafile.
+//!
+//! Rows are split on '|' and the file on '\n', so any of those in a
+//! path (and '%' itself, and '\r') are written as `%XX` hex escapes.
+//! So are any bytes that are not valid UTF-8, which keeps the datafile
+//! readable text while still holding every path the kernel accepts.
+
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+/// Escape a path so it can be written as one field of one row.
+pub fn encode_path(path: &Path) -> String {
+    let bytes = path_bytes(path);
+    let mut encoded = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        for ch in chunk.valid().chars() {
+            match ch {
+                '%' | '|' | '\n' | '\r' => encoded.push_str(&format!("%{:02X}", ch as u32)),
+                _ => encoded.push(ch),
+            }
+        }
+        for byte in chunk.invalid() {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Undo encode_path; None if the field has a broken escape.
+pub fn decode_path(field: &str) -> Option<PathBuf> {
+    let bytes = field.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = field.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    path_from_bytes(decoded)
+}
+
+/// The raw bytes of a path, as the kernel sees them.
+#[cfg(unix)]
+pub fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(path.as_os_str().as_bytes())
+}
+
+#[cfg(not(unix))]
+pub fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    match path.to_string_lossy() {
+        Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+        Cow::Owned(s) => Cow::Owned(s.into_bytes()),
+    }
+}
+
+/// A path made from raw bytes, as read back from a datafile.
+#[cfg(unix)]
+pub fn path_from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+    Some(PathBuf::from(std::ffi::OsString::from_vec(bytes)))
+}
+
+#[cfg(not(unix))]
+pub fn path_from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_path() {
+        assert_eq!(encode_path(Path::new("/home/tim/tmp")), "/home/tim/tmp");
+        assert_eq!(encode_path(Path::new("/a|b\nc%d")), "/a%7Cb%0Ac%25d");
+        assert_eq!(decode_path("/a%7Cb%0Ac%25d").unwrap(), Path::new("/a|b\nc%d"));
+        assert!(decode_path("/a%7").is_none());
+        assert!(decode_path("/a%zz").is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_encode_non_utf8_path() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(OsStr::from_bytes(b"/home/caf\xe9/\xff\xfe"));
+        let encoded = encode_path(path);
+        assert_eq!(encoded, "/home/caf%E9/%FF%FE");
+        assert_eq!(decode_path(&encoded).unwrap(), path);
+    }
+}
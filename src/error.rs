@@ -0,0 +1,115 @@
+//! Errors returned by the library, and the exit status for each kind.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum RasdfError {
+    /// The command line can't be understood.
+    Usage(String),
+    /// A datafile or journal that can't be read as one; line is the
+    /// line of a text file the problem is on, if known.
+    Parse {
+        path: PathBuf,
+        line: Option<usize>,
+        message: String,
+    },
+    /// A datafile written by a later version of rasdf.
+    NewerVersion { path: PathBuf, version: u32 },
+    /// Not allowed to read or write path.
+    Permission { path: PathBuf, error: io::Error },
+    /// Any other failure reading or writing path.
+    Io { path: PathBuf, error: io::Error },
+    /// Another rasdf held the lock for longer than lock_timeout ms.
+    LockTimeout { path: PathBuf, timeout: u64 },
+}
+
+impl RasdfError {
+    /// An I/O error on path, sorting out the ones that are down to permissions.
+    pub fn io<P: AsRef<Path>>(path: P, error: io::Error) -> RasdfError {
+        let path = path.as_ref().to_path_buf();
+        match error.kind() {
+            io::ErrorKind::PermissionDenied => RasdfError::Permission { path, error },
+            _ => RasdfError::Io { path, error },
+        }
+    }
+
+    /// A problem with the contents of path, somewhere not yet known.
+    pub fn parse<P: AsRef<Path>>(path: P, message: String) -> RasdfError {
+        RasdfError::Parse {
+            path: path.as_ref().to_path_buf(),
+            line: None,
+            message,
+        }
+    }
+
+    /// Place a parse error on a line of its file; other errors are unchanged.
+    pub fn at_line(self, line: usize) -> RasdfError {
+        match self {
+            RasdfError::Parse { path, message, .. } => RasdfError::Parse {
+                path,
+                line: Some(line),
+                message,
+            },
+            other => other,
+        }
+    }
+
+    /// Status for the process to exit with: 2 for the command line,
+    /// and 3 for anything wrong with the datafile, its journal or lock.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RasdfError::Usage(_) => 2,
+            RasdfError::Parse { .. }
+            | RasdfError::NewerVersion { .. }
+            | RasdfError::Io { .. }
+            | RasdfError::Permission { .. }
+            | RasdfError::LockTimeout { .. } => 3,
+        }
+    }
+}
+
+impl fmt::Display for RasdfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RasdfError::Usage(message) => write!(f, "{}", message),
+            RasdfError::Parse {
+                path,
+                line: Some(line),
+                message,
+            } => write!(f, "{}: line {}: {}", path.display(), line, message),
+            RasdfError::Parse {
+                path,
+                line: None,
+                message,
+            } => write!(f, "{}: {}", path.display(), message),
+            RasdfError::NewerVersion { path, version } => write!(
+                f,
+                "{} is format v{}, newer than this rasdf supports (v{})",
+                path.display(),
+                version,
+                crate::DATAFILE_VERSION
+            ),
+            RasdfError::Permission { path, error } | RasdfError::Io { path, error } => {
+                write!(f, "{}: {}", path.display(), error)
+            }
+            RasdfError::LockTimeout { path, timeout } => write!(
+                f,
+                "timed out after {}ms waiting for lock on {}",
+                timeout,
+                path.display()
+            ),
+        }
+    }
+}
+
+impl Error for RasdfError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RasdfError::Permission { error, .. } | RasdfError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! Fuzzy matching: the terms, in order, as a subsequence of the path.
+//!
+//! Any bytes may come between the ones matched, but a match is worth
+//! more where its bytes start a segment or a word, or follow straight
+//! on from the one before, so `rsdf` matches `rust/rasdf` better at
+//! `rasdf` than at `rust`. The best match is found in one pass per
+//! byte of the terms, as this runs for every row at every find.
+
+use crate::query::{segment_end, segment_start, Term};
+
+/// Worth of every byte matched.
+const BYTE: f32 = 1.0;
+/// Extra for a byte at the start of a segment.
+const SEGMENT_START: f32 = 2.0;
+/// Extra for a byte at the start of a word within a segment.
+const WORD_START: f32 = 1.0;
+/// Extra for a byte straight after the one before it in the same term.
+const CONSECUTIVE: f32 = 1.5;
+/// Cost of each byte skipped between two matched bytes...
+const GAP: f32 = 0.1;
+/// ...up to this much, however long the gap.
+const GAP_MAX: f32 = 0.5;
+
+/// Bytes that end a word within a segment.
+const WORD_SEPARATORS: &[u8] = b"-_. ";
+
+/// How well the terms match path, between 0 and 1 (a perfect match);
+/// None if they are not a subsequence of it. folded is the path
+/// lower-cased. If strict, the last term has to end in the last
+/// segment; if segments, each term has to start in a later segment than
+/// the one before ended.
+pub fn quality(
+    path: &[u8],
+    folded: &[u8],
+    terms: &[Term],
+    strict: bool,
+    segments: bool,
+) -> Option<f32> {
+    // each byte of the terms (but not the exclusions), whether it
+    // starts a term, where to look for it, and where it may be
+    let query: Vec<(u8, bool, &[u8], Anchor)> = terms
+        .iter()
+        .filter(|term| !term.exclude)
+        .flat_map(|term| {
+            let haystack = term.haystack(path, folded);
+            let last = term.bytes.len().saturating_sub(1);
+            term.bytes.iter().enumerate().map(move |(i, &b)| {
+                let anchor = Anchor {
+                    start: i == 0 && term.at_start(),
+                    end: i == last && term.at_end(),
+                };
+                (b, i == 0, haystack, anchor)
+            })
+        })
+        .collect();
+    if query.is_empty() {
+        return Some(1.0);
+    }
+
+    // the worth of matching a byte at each place, as far as it goes
+    // without the byte before
+    let worth: Vec<f32> = (0..path.len())
+        .map(|j| match j.checked_sub(1).map(|i| path[i]) {
+            None | Some(b'/') => BYTE + SEGMENT_START,
+            Some(b) if WORD_SEPARATORS.contains(&b) => BYTE + WORD_START,
+            Some(_) => BYTE,
+        })
+        .collect();
+
+    // best[j]: the best total for the bytes of the query so far, with
+    // the last of them matched at path[j]
+    let (first, _, haystack, anchor) = query[0];
+    let mut best: Vec<f32> = (0..path.len())
+        .map(|j| match haystack[j] == first && anchor.allows(path, j) {
+            true => worth[j],
+            false => f32::NEG_INFINITY,
+        })
+        .collect();
+
+    for &(byte, term_start, haystack, anchor) in &query[1..] {
+        // running maxima of best[k] + GAP * k and of best[k], so that
+        // the best earlier match less the charge for the gap from it is
+        // found in one step
+        let mut gapped = Vec::with_capacity(path.len());
+        let mut earlier = Vec::with_capacity(path.len());
+        let (mut g, mut e) = (f32::NEG_INFINITY, f32::NEG_INFINITY);
+        for (k, &total) in best.iter().enumerate() {
+            g = g.max(total + GAP * k as f32);
+            e = e.max(total);
+            gapped.push(g);
+            earlier.push(e);
+        }
+
+        let mut next = vec![f32::NEG_INFINITY; path.len()];
+        let mut last_separator = None;
+        for j in 1..path.len() {
+            if path[j - 1] == b'/' {
+                last_separator = Some(j - 1);
+            }
+            if haystack[j] != byte || !anchor.allows(path, j) {
+                continue;
+            }
+            // the last place the byte before may be; with segments, a
+            // new term has to start in a later segment than it
+            let limit = match (segments && term_start, last_separator) {
+                (false, _) => j - 1,
+                (true, Some(separator)) => separator,
+                (true, None) => continue,
+            };
+            let after_gap = (gapped[limit] - GAP * (j - 1) as f32).max(earlier[limit] - GAP_MAX);
+            let adjacent = match term_start {
+                true => f32::NEG_INFINITY,
+                false => best[j - 1] + CONSECUTIVE,
+            };
+            next[j] = after_gap.max(adjacent) + worth[j];
+        }
+        best = next;
+    }
+
+    let last_segment = match strict {
+        true => path.iter().rposition(|&b| b == b'/').unwrap_or(0),
+        false => 0,
+    };
+    let total = best[last_segment..]
+        .iter()
+        .cloned()
+        .fold(f32::NEG_INFINITY, f32::max);
+    if total == f32::NEG_INFINITY {
+        return None;
+    }
+    // at best, each term is a whole run from the start of a segment
+    let most: f32 = query
+        .iter()
+        .map(|&(_, term_start, _, _)| match term_start {
+            true => BYTE + SEGMENT_START,
+            false => BYTE + CONSECUTIVE,
+        })
+        .sum();
+    Some((total / most).min(1.0))
+}
+
+/// Where a byte of the query has to be matched, for a term with `^`
+/// or `$`
+#[derive(Copy, Clone)]
+struct Anchor {
+    /// at the start of a segment
+    start: bool,
+    /// at the end of a segment
+    end: bool,
+}
+
+impl Anchor {
+    fn allows(&self, path: &[u8], j: usize) -> bool {
+        (!self.start || segment_start(path, j)) && (!self.end || segment_end(path, j + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(terms: &[&str]) -> Vec<Term> {
+        terms
+            .iter()
+            .map(|t| Term {
+                bytes: t.as_bytes().to_vec(),
+                folded: false,
+                exclude: false,
+                pattern: crate::query::Pattern::Literal {
+                    at_start: false,
+                    at_end: false,
+                },
+            })
+            .collect()
+    }
+
+    fn quality(path: &[u8], terms: &[Term], strict: bool) -> Option<f32> {
+        super::quality(path, path, terms, strict, false)
+    }
+
+    #[test]
+    fn test_subsequence() {
+        let path = b"/home/me/Programming/rust/rasdf";
+        assert!(quality(path, &terms(&["rsdf"]), true).is_some());
+        assert!(quality(path, &terms(&["Prog", "rsdf"]), true).is_some());
+        assert!(quality(path, &terms(&["rsdf", "prog"]), false).is_none());
+        assert!(quality(path, &terms(&["rsdfx"]), false).is_none());
+        assert_eq!(quality(path, &terms(&[]), true), Some(1.0));
+    }
+
+    #[test]
+    fn test_strict() {
+        let path = b"/home/me/rust/src";
+        assert!(quality(path, &terms(&["rst"]), false).is_some());
+        assert!(quality(path, &terms(&["rst"]), true).is_none());
+        assert!(quality(path, &terms(&["rst", "s"]), true).is_some());
+    }
+
+    #[test]
+    fn test_segments() {
+        let path = b"/home/me/myfile.txt";
+        let q = |t: &[&str], segments| super::quality(path, path, &terms(t), false, segments);
+        assert!(q(&["my", "ile", "tx"], false).is_some());
+        assert!(q(&["my", "ile", "tx"], true).is_none());
+        assert!(q(&["hm", "mfl"], true).is_some());
+        // the bytes of one term may still cross segments
+        assert!(q(&["eme"], true).is_some());
+    }
+
+    #[test]
+    fn test_ranking() {
+        let q = |path: &[u8], t: &[&str]| quality(path, &terms(t), false).unwrap();
+        // segment starts beat the middle of words
+        assert!(q(b"/src/rasdf", &["rasdf"]) > q(b"/src/prasdf", &["rasdf"]));
+        // runs beat scattered bytes
+        assert!(q(b"/xrsdf", &["rsdf"]) > q(b"/xrxsxdxf", &["rsdf"]));
+        // word starts beat the middle of words
+        assert!(q(b"/my-files", &["mf"]) > q(b"/mxfiles", &["mf"]));
+        // a whole segment matched exactly is as good as it gets
+        assert_eq!(q(b"/x/a", &["a"]), 1.0);
+    }
+}
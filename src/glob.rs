@@ -0,0 +1,146 @@
+//! Shell-style glob patterns, matched against paths as bytes.
+//!
+//! `?` matches any one byte and `*` any run of bytes, but neither
+//! matches a path separator; `**` matches anything at all, separators
+//! included. `[abc]`, `[a-z]` and `[!abc]` match one byte from (or not
+//! from) a set.
+
+use std::path::MAIN_SEPARATOR;
+
+const SEP: u8 = MAIN_SEPARATOR as u8;
+
+/// true if the whole of text matches pattern
+///
+/// Rather than trying every length for every star, which takes
+/// exponential time on patterns like `*a*a*a*b`, only the last `*` and
+/// the last `**` are ever taken back: a later star can take up anything
+/// an earlier one could, except that a `*` stops at a separator, when
+/// it is the `**` before it that has to take more.
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // the pattern after the last `*` and `**`, and where in the text
+    // what follows them starts
+    let mut star: Option<(usize, usize)> = None;
+    let mut globstar: Option<(usize, usize)> = None;
+    loop {
+        if pattern.get(p) == Some(&b'*') {
+            if pattern.get(p + 1) == Some(&b'*') {
+                p += 2;
+                globstar = Some((p, t));
+                star = None;
+            } else {
+                p += 1;
+                star = Some((p, t));
+            }
+            continue;
+        }
+        if p == pattern.len() && t == text.len() {
+            return true;
+        }
+        if let Some(len) = text.get(t).and_then(|&b| one_byte(&pattern[p..], b)) {
+            p += len;
+            t += 1;
+            continue;
+        }
+
+        // no match here: the last `*` takes one more byte, if it can,
+        // or else the last `**` does
+        match (star, globstar) {
+            (Some((after, taken)), _) if text.get(taken).is_some_and(|&b| b != SEP) => {
+                star = Some((after, taken + 1));
+                (p, t) = (after, taken + 1);
+            }
+            (_, Some((after, taken))) if taken < text.len() => {
+                globstar = Some((after, taken + 1));
+                star = None;
+                (p, t) = (after, taken + 1);
+            }
+            _ => return false,
+        }
+    }
+}
+
+/// If the start of pattern (up to the next star) matches the byte b,
+/// the length of pattern it takes up.
+fn one_byte(pattern: &[u8], b: u8) -> Option<usize> {
+    match pattern.split_first()? {
+        (b'?', _) => (b != SEP).then_some(1),
+        (b'[', rest) => match class(rest) {
+            Some((set, negated, after)) => {
+                (b != SEP && in_class(set, b) != negated).then_some(pattern.len() - after.len())
+            }
+            // no closing ']': just a plain '['
+            None => (b == b'[').then_some(1),
+        },
+        (&p, _) => (p == b).then_some(1),
+    }
+}
+
+/// Read a `[...]` class (after the '['); returns the set, whether it is
+/// negated and the rest of the pattern, or None if it is not closed.
+fn class(pattern: &[u8]) -> Option<(&[u8], bool, &[u8])> {
+    let (negated, pattern) = match pattern.first() {
+        Some(b'!') | Some(b'^') => (true, &pattern[1..]),
+        _ => (false, pattern),
+    };
+    // a ']' straight after the '[' is part of the set
+    let end = 1 + pattern.get(1..)?.iter().position(|&b| b == b']')?;
+    Some((&pattern[..end], negated, &pattern[end + 1..]))
+}
+
+/// true if b is in a set of bytes and ranges such as `a-z`
+fn in_class(set: &[u8], b: u8) -> bool {
+    let mut i = 0;
+    while i < set.len() {
+        if i + 2 < set.len() && set[i + 1] == b'-' {
+            if set[i] <= b && b <= set[i + 2] {
+                return true;
+            }
+            i += 3;
+        } else {
+            if set[i] == b {
+                return true;
+            }
+            i += 1;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_match(pattern: &str, text: &str) -> bool {
+        glob_match(pattern.as_bytes(), text.as_bytes())
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(is_match("/tmp/*", "/tmp/foo"));
+        assert!(!is_match("/tmp/*", "/tmp/foo/bar"));
+        assert!(is_match("/tmp/**", "/tmp/foo/bar"));
+        assert!(is_match("**/.git", "/home/tim/rasdf/.git"));
+        assert!(is_match("/home/?im", "/home/tim"));
+        assert!(!is_match("/home/?im", "/home/tiim"));
+        assert!(is_match("*.[ch]", "main.c"));
+        assert!(is_match("*.[a-z]s", "lib.rs"));
+        assert!(!is_match("*.[!c]", "main.c"));
+        assert!(is_match("a[b", "a[b"));
+        assert!(!is_match("foo", "foobar"));
+        assert!(is_match("**/a*b", "x/a/y/acb"));
+        assert!(!is_match("*/*x", "a/b/x"));
+        assert!(is_match("**a*", "ab/a"));
+        assert!(is_match("/x/**", "/x/"));
+        assert!(is_match("**", ""));
+    }
+
+    #[test]
+    fn test_glob_backtracking() {
+        // each of these takes far too long if every star tries every length
+        let text = "a".repeat(60);
+        assert!(!is_match("*a*a*a*a*a*a*a*a*a*a*b", &text));
+        assert!(!is_match("**a**a**a**a**a**a**a**a**b", &text));
+        assert!(is_match("**a*a*a*a*a*a*a*a*a", &text));
+    }
+}
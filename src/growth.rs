@@ -0,0 +1,73 @@
+//! Growth policies: how much a visit adds to the rating of a path.
+//!
+//! The rating a row already has is taken as it is, whichever policy
+//! wrote it, so the policy can be changed on an existing datafile.
+
+use crate::config::Growth;
+
+/// The rating after a visit worth increment, from rating.
+pub fn grow(growth: Growth, cap: f32, rating: f32, increment: f32) -> f32 {
+    // a rating from a damaged or hand-edited row starts again from nothing
+    let rating = match rating.is_finite() && rating > 0.0 {
+        true => rating,
+        false => 0.0,
+    };
+    match growth {
+        // each visit adds less as the rating grows: about sqrt(2n) after n
+        Growth::Reciprocal if rating == 0.0 => increment,
+        Growth::Reciprocal => rating + increment / rating,
+        // each visit adds the same, as in fasd
+        Growth::Linear => rating + increment,
+        // each visit adds a little less, but never so little as reciprocal
+        Growth::Log => rating + increment / (1.0 + rating.max(1.0).ln()),
+        // linear up to the cap; a rating already above it is not cut down
+        Growth::Capped => rating.max((rating + increment).min(cap)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The rating after n visits from a first one of 1.
+    fn after(growth: Growth, n: usize) -> f32 {
+        (1..n).fold(1.0, |rating, _| grow(growth, 100.0, rating, 1.0))
+    }
+
+    #[test]
+    fn test_reciprocal() {
+        assert_eq!(grow(Growth::Reciprocal, 100.0, 4.0, 1.0), 4.25);
+        assert!((after(Growth::Reciprocal, 1000) - 2000f32.sqrt()).abs() < 1.0);
+        assert!(after(Growth::Reciprocal, 1000) - after(Growth::Reciprocal, 900) < 3.0);
+    }
+
+    #[test]
+    fn test_linear() {
+        assert_eq!(after(Growth::Linear, 1000), 1000.0);
+        assert_eq!(grow(Growth::Linear, 100.0, 2.5, 1.0), 3.5);
+    }
+
+    #[test]
+    fn test_log() {
+        // slower than linear, but well clear of reciprocal, and still rising
+        let rating = after(Growth::Log, 1000);
+        assert!(rating < 1000.0 && rating > 4.0 * after(Growth::Reciprocal, 1000));
+        assert!(after(Growth::Log, 1000) - after(Growth::Log, 900) > 10.0);
+    }
+
+    #[test]
+    fn test_capped() {
+        assert_eq!(after(Growth::Capped, 50), 50.0);
+        assert_eq!(after(Growth::Capped, 1000), 100.0);
+        assert_eq!(grow(Growth::Capped, 100.0, 250.0, 1.0), 250.0);
+    }
+
+    #[test]
+    fn test_bad_ratings() {
+        for growth in [Growth::Reciprocal, Growth::Linear, Growth::Log, Growth::Capped] {
+            for rating in [0.0, -3.0, f32::NAN, f32::INFINITY] {
+                assert_eq!(grow(growth, 100.0, rating, 1.0), 1.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! Append-only journal of visits, kept beside the datafile.
+//!
+//! `add` runs on every prompt, so rather than rewriting the whole
+//! datafile it appends one `path|date` row per path to
+//! `<datafile>.journal`. RasdfBase::from_file replays the journal over
+//! the datafile, and every full write of the datafile merges it in and
+//! removes it.
+//!
+//! The journal starts with a `#journal <generation>` line matching the
+//! `journal=` field of the datafile it belongs to. A journal left over
+//! from an earlier generation (if rasdf stopped between writing the
+//! datafile and removing the journal) has been merged already, so it is
+//! ignored and replaced.
+
+use crate::config::Config;
+use crate::encoding::{decode_path, encode_path};
+use crate::error::RasdfError;
+use crate::logging::log_only;
+use crate::{path_to_add, RasdfBase};
+
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// The journal for a datafile: same name with `.journal` added.
+pub fn journal_path(datafile: &Path) -> PathBuf {
+    let mut name = datafile.as_os_str().to_os_string();
+    name.push(".journal");
+    PathBuf::from(name)
+}
+
+/// Append a visit to each of paths; returns the number of paths
+/// journalled and the new size of the journal.
+///
+/// Paths that don't exist, are ignored or can't be looked at are
+/// skipped. The datafile lock must be held.
+pub fn append(conf: &Config, paths: &[OsString]) -> Result<(usize, u64), RasdfError> {
+    // Err for a datafile from a later version, as well as an unreadable one
+    let header = RasdfBase::header_from_file(conf)?;
+
+    let path = journal_path(&conf.datafile);
+    append_rows(conf, &path, paths, header.journal()).map_err(|e| RasdfError::io(&path, e))
+}
+
+fn append_rows(
+    conf: &Config,
+    path: &Path,
+    paths: &[OsString],
+    journal: u64,
+) -> io::Result<(usize, u64)> {
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .append(true)
+        .open(path)?;
+
+    // start afresh if the journal is new or stale
+    let generation = format!("#journal {}\n", journal);
+    let mut first_line = vec![0; generation.len()];
+    if file.read_exact(&mut first_line).is_err() || first_line != generation.as_bytes() {
+        file.set_len(0)?;
+        file.write_all(generation.as_bytes())?;
+    } else {
+        drop_partial_row(&mut file)?;
+    }
+
+    // one write for all the rows, so a crash leaves at most one partial row
+    let mut rows = String::new();
+    let mut count = 0;
+    for arg in paths {
+        match path_to_add(conf, arg) {
+            Ok(Some(pathbuf)) => {
+                log_only(conf, &format!("Journalling path: {}", pathbuf.display()));
+                rows.push_str(&format!("{}|{}\n", encode_path(&pathbuf), conf.current_time));
+                count += 1;
+            }
+            Ok(None) => (),
+            // the arguments are a whole command line, not all of them ours to read
+            Err(e) => log_only(conf, &format!("Skipping path: {}", e)),
+        }
+    }
+    file.write_all(rows.as_bytes())?;
+    file.sync_data()?;
+
+    Ok((count, file.metadata()?.len()))
+}
+
+/// Cut off a last row left without its newline by a crash, as replay
+/// would ignore it, so that the next row is not appended to it.
+fn drop_partial_row(file: &mut fs::File) -> io::Result<()> {
+    let mut last = [0; 1];
+    file.seek(SeekFrom::End(-1))?;
+    file.read_exact(&mut last)?;
+    if last[0] == b'\n' {
+        return Ok(());
+    }
+    let mut contents = Vec::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_end(&mut contents)?;
+    let keep = contents.iter().rposition(|&b| b == b'\n').map_or(0, |n| n + 1);
+    file.set_len(keep as u64)
+}
+
+/// Replay the visits in the journal over the database; Err if the
+/// journal can't be read, so that it is not merged and lost.
+pub fn replay(conf: &Config, dbase: &mut RasdfBase) -> Result<(), RasdfError> {
+    let journal = journal_path(&conf.datafile);
+    let contents = match fs::read_to_string(&journal) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(RasdfError::io(&journal, e)),
+    };
+    let mut lines = contents.split('\n').zip(1..);
+
+    // a journal from another generation is already merged, or not ours
+    if lines.next().map(|(line, _)| line) != Some(&format!("#journal {}", dbase.journal())) {
+        return Ok(());
+    }
+
+    // the final piece has no newline: empty, or a row cut short by a crash
+    let mut lines = lines.peekable();
+    while let Some((line, n)) = lines.next() {
+        if lines.peek().is_none() {
+            break;
+        }
+        let (path, date) = line
+            .split_once('|')
+            .and_then(|(path, date)| Some((decode_path(path)?, date.parse().ok()?)))
+            .ok_or_else(|| {
+                RasdfError::parse(&journal, format!("can't parse journal row: {}", line)).at_line(n)
+            })?;
+        dbase.visit(conf, path, date);
+    }
+    Ok(())
+}
+
+/// Remove the journal once it has been merged into the datafile.
+pub fn remove(conf: &Config) -> Result<(), RasdfError> {
+    let path = journal_path(&conf.datafile);
+    match fs::remove_file(&path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(RasdfError::io(&path, e)),
+        _ => Ok(()),
+    }
+}
@@ -1,16 +1,47 @@
-use std::env;
 use std::fs;
-use std::io::Write;
+use std::io::{self, Read};
 
+use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::collections::HashMap;
-use std::path::{PathBuf, MAIN_SEPARATOR};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+pub mod bookmarks;
+
+pub mod cli;
 
 pub mod config;
-use config::{home_dir, Config, ScoreMethod};
+use config::{home_dir, Backend, Config, Matching, ScoreMethod};
+
+pub mod config_file;
+
+pub mod decay;
+
+pub mod encoding;
+use encoding::{decode_path, path_bytes};
+
+pub mod error;
+use error::RasdfError;
+
+pub mod fuzzy;
+
+pub mod glob;
+
+pub mod growth;
+
+pub mod journal;
+
+pub mod lock;
 
 pub mod logging;
-use logging::{log, log_only};
+use logging::log_only;
+
+pub mod query;
+use query::Term;
+
+pub mod storage;
 
 // TODO: Replace RasdfBase with RasdfBase throughout code base...
 
@@ -20,13 +51,16 @@ use logging::{log, log_only};
 /// fields:
 /// + rating: float, continually updated when cleaning file
 /// + date: u64, a UNIX-style datestamp of last access
-/// + flags: string, use to be determined
+/// + flags: string of single-character flags, eg REMOVABLE
+/// + visits: u64 datestamps of the most recent visits, oldest first;
+///   at most conf.history of them
 ///
 #[derive(Debug)]
 pub struct RasdfBaseData {
     pub rating: f32,
     pub date: u64,
     pub flags: String,
+    pub visits: Vec<u64>,
 }
 
 impl RasdfBaseData {
@@ -38,11 +72,14 @@ impl RasdfBaseData {
             rating: opt_rating.unwrap_or(1.0),
             date: opt_date.unwrap_or(conf.current_time),
             flags: flags.to_string(),
+            visits: Vec::new(),
         }
     }
 
-    pub fn update_with(&mut self, other: &RasdfBaseData) {
-        self.rating += other.rating / self.rating;
+    /// Merge in a visit (or another row for the same path), growing the
+    /// rating by the policy set in conf.growth.
+    pub fn update_with(&mut self, conf: &Config, other: &RasdfBaseData) {
+        self.rating = growth::grow(conf.growth, conf.rating_cap, self.rating, other.rating);
         self.date = std::cmp::max(self.date, other.date);
         self.flags = {
             let mut set: Vec<char> = Vec::new();
@@ -54,40 +91,96 @@ impl RasdfBaseData {
             }
             set.iter().collect()
         };
+        self.visits.extend_from_slice(&other.visits);
+        self.visits.sort_unstable();
+    }
+
+    /// true if the row has the given flag
+    pub fn has_flag(&self, flag: char) -> bool {
+        self.flags.contains(flag)
+    }
+
+    /// Drop all but the latest history visits.
+    fn keep_visits(&mut self, history: usize) {
+        let excess = self.visits.len().saturating_sub(history);
+        self.visits.drain(..excess);
     }
 
     pub fn score(&self, conf: &Config) -> f32 {
         match conf.method {
             ScoreMethod::Date => self.date as f32,
             ScoreMethod::Rating => self.rating,
-            ScoreMethod::Frecency => {
-                let scale: f32 = match conf.current_time - self.date {
-                        0..=3600 => 6.0,        // less than an hour
-                        3601..=86400 => 4.0,    // up to one day
-                        86401..=604800 => 2.0,  // up to seven days
-                        _ => 1.0,               // otherwise...
-                    };
-                scale * self.rating
+            ScoreMethod::Frecency => self.frecency(conf, self.date) * self.rating,
+            // rows from before visits were recorded count as one visit
+            ScoreMethod::Visits if self.visits.is_empty() => self.frecency(conf, self.date),
+            ScoreMethod::Visits => self.visits.iter().map(|&date| self.frecency(conf, date)).sum(),
+            ScoreMethod::Recent => {
+                let since = conf.current_time.saturating_sub(conf.recent_days * 86400);
+                match self.visits.is_empty() {
+                    true if self.date >= since => 1.0,
+                    true => 0.0,
+                    false => self.visits.iter().filter(|&&date| date >= since).count() as f32,
+                }
             }
         }
     }
+
+    /// The decay factor for a visit on date.
+    fn frecency(&self, conf: &Config, date: u64) -> f32 {
+        let age = conf.current_time.saturating_sub(date);
+        decay::decay(conf.decay).factor(conf, age)
+    }
+}
+
+/// Row flag for a pinned path: never aged or dropped by clean, and
+/// ranked above every unpinned match.
+pub const PINNED: char = 'p';
+
+/// Row flag for a hidden path: never found.
+pub const HIDDEN: char = 'h';
+
+/// Row flag for a path on a removable or network mount: kept by clean
+/// while it is missing.
+pub const REMOVABLE: char = 'r';
+
+/// Any other letter or digit in the flags is a tag of the user's own.
+pub fn is_flag(flag: char) -> bool {
+    flag.is_ascii_alphanumeric()
 }
 
+/// Version of the datafile format written by this build.
+///
+/// v1: headerless rows of `path|rating|date|flags`
+/// v2: the same rows after a `#rasdf v2` header line
+/// v3: as v2, with paths escaped by encoding::encode_path
+/// v4: the header also carries key=value fields, eg `journal=<generation>`
+/// v5: rows may have a fifth field, the recent visits as comma-separated dates
+pub const DATAFILE_VERSION: u32 = 5;
+
 /// RasdfBase
 ///
 /// Database of all the mappings
 /// path -> RasdfBaseData
 ///
-/// path is maintained as absolute canonical String
+/// path is maintained as absolute canonical PathBuf, which need not
+/// be valid UTF-8
 ///
 pub struct RasdfBase {
-    contents: HashMap<String, RasdfBaseData>,
+    contents: HashMap<PathBuf, RasdfBaseData>,
+    version: u32,
+    backend: Backend,
+    journal: u64,
+    cleaned: u64,
 }
 
 impl RasdfBase {
     pub fn new() -> RasdfBase {
         RasdfBase {
             contents: HashMap::new(),
+            version: DATAFILE_VERSION,
+            backend: Backend::Text,
+            journal: 0,
+            cleaned: 0,
         }
     }
 }
@@ -109,128 +202,294 @@ impl RasdfBase {
         self.contents.is_empty()
     }
 
+    /// format version of the datafile this was read from
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// format the database will be written in
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// change the format the database will be written in
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+    }
+
+    /// generation of the journal that has still to be merged into the datafile
+    pub fn journal(&self) -> u64 {
+        self.journal
+    }
+
+    /// date the ratings were last aged by clean, or 0 if never
+    pub fn cleaned(&self) -> u64 {
+        self.cleaned
+    }
+
     /// return basedata for given path, or None
-    pub fn entry(&self, path: &str) -> Option<&RasdfBaseData> {
-        self.contents.get(path)
+    pub fn entry<P: AsRef<Path>>(&self, path: P) -> Option<&RasdfBaseData> {
+        self.contents.get(path.as_ref())
     }
 
-    /// add or update a path record in the database
-    pub fn add_path(&mut self, conf: &Config, path: &str) {
-        let Some(pathstring) = canonical_string(path)     // Option<PathBuf>
-            .map(|pb| pb.into_os_string())               // Option<OsString>
-            .and_then(|s| s.into_string().ok())          // Option<String>
-        else {
-            return;
+    /// add or update a path record in the database; false if the path
+    /// does not exist or is ignored
+    pub fn add_path<P: AsRef<Path>>(&mut self, conf: &Config, path: P) -> Result<bool, RasdfError> {
+        let Some(pathbuf) = path_to_add(conf, path)? else {
+            return Ok(false);
+        };
+        self.visit(conf, pathbuf, conf.current_time);
+        Ok(true)
+    }
+
+    /// record a visit at the given date to a path already made canonical
+    fn visit(&mut self, conf: &Config, pathbuf: PathBuf, date: u64) {
+        let flags = match conf.is_removable(&pathbuf) {
+            true => REMOVABLE.to_string(),
+            false => String::new(),
         };
+        let mut visit = RasdfBaseData::new(conf, Some(1.0), Some(date), &flags);
+        visit.visits.push(date);
 
-        // check if pathstring already exists:
-        if let Some(data) = self.contents.get_mut(&pathstring) {
+        // check if pathbuf already exists:
+        if let Some(data) = self.contents.get_mut(&pathbuf) {
             // it's there, increment the rating.
-            log_only(conf, &format!("Uprating path: {}", pathstring));
-            data.update_with(&RasdfBaseData::new(&conf, Some(1.0), None, ""));
+            log_only(conf, &format!("Uprating path: {}", pathbuf.display()));
+            data.update_with(conf, &visit);
+            data.keep_visits(conf.history);
         } else {
             // new path, add it to the database
-            log_only(conf, &format!("Adding new path: {}", pathstring));
-            self.contents.insert(pathstring,
-                RasdfBaseData::new(&conf, Some(1.0), None, ""));
+            log_only(conf, &format!("Adding new path: {}", pathbuf.display()));
+            visit.keep_visits(conf.history);
+            self.contents.insert(pathbuf, visit);
         }
     }
 
-    pub fn remove(&mut self, conf: &Config) {
-        if self.contents.remove(&conf.arguments[0]).is_none() {
-            log(
-                conf,
-                &format!("Could not find row to remove: {}", &conf.arguments[0]),
-            );
+    /// return the row for path, made canonical if it still exists
+    pub fn lookup<P: AsRef<Path>>(&self, path: P) -> Option<(&Path, &RasdfBaseData)> {
+        let path = path.as_ref();
+        let key = canonical_string(path).unwrap_or_else(|| path.to_path_buf());
+        self.contents
+            .get_key_value(&key)
+            .map(|(path, data)| (path.as_path(), data))
+    }
+
+    /// set (or with add false, clear) each of flags on the row for path;
+    /// false if there is no row
+    pub fn set_flags<P: AsRef<Path>>(&mut self, path: P, flags: &str, add: bool) -> bool {
+        let path = path.as_ref();
+        let key = canonical_string(path).unwrap_or_else(|| path.to_path_buf());
+        let Some(data) = self.contents.get_mut(&key) else {
+            return false;
+        };
+        for flag in flags.chars() {
+            match (add, data.has_flag(flag)) {
+                (true, false) => data.flags.push(flag),
+                (false, true) => data.flags.retain(|f| f != flag),
+                _ => (),
+            }
         }
+        true
+    }
+
+    /// remove the row for path; false if there was none
+    pub fn remove<P: AsRef<Path>>(&mut self, path: P) -> bool {
+        // rows are keyed by canonical path, but the path may have gone
+        let path = path.as_ref();
+        let key = canonical_string(path).unwrap_or_else(|| path.to_path_buf());
+        self.contents.remove(&key).is_some()
     }
 
     // add one row given as a string to self.contents; return new length of contents
-    pub fn add_line(&mut self, conf: &Config, row: &str) -> usize {
+    pub fn add_line(&mut self, conf: &Config, row: &str) -> Result<usize, RasdfError> {
         // ignore a blank line
         if row.is_empty() {
-            return self.contents.len();
+            return Ok(self.contents.len());
         }
+        let bad_row = || RasdfError::parse(&conf.datafile, format!("can't parse row: {}", row));
 
         let mut v: Vec<&str> = row.split('|').collect();
-        // if there are only three elements, add empty one at the end
+        // if there are only three elements, add empty one at the end;
+        // likewise for the visits from v5 onwards
         if v.len() == 3 {
             v.push("");
         }
-        if v.len() != 4 {
+        if v.len() == 4 {
+            v.push("");
+        }
+        if v.len() != 5 || (self.version < 5 && !v[4].is_empty()) {
             // not legal line
-            log(conf, &format!("Can't parse row: {}", row));
-            return self.contents.len();
+            return Err(bad_row());
         }
 
-        // get a valid path string from v[0]
-        let Some(pathstring) = canonical_string(v[0])      // Option<PathBuf>
-                .map(|pb| pb.into_os_string())             // Option<OsString>
-                .and_then(|s| s.into_string().ok())        // Option<String>
-        else {
-            log_only(conf, &format!("Cannot parse path: {}", v[0]));
-            return self.contents.len();
+        // paths are escaped from v3 onwards
+        let path = if self.version >= 3 {
+            decode_path(v[0]).ok_or_else(bad_row)?
+        } else {
+            PathBuf::from(v[0])
         };
 
-        // check the other three fields
-        let (Ok(rating), Ok(date), flags) =
-            (v[1].parse::<f32>(), v[2].parse::<u64>(), v[3].to_string())
+        // check the other four fields
+        let visits: Result<Vec<u64>, _> = v[4]
+            .split(',')
+            .filter(|date| !date.is_empty())
+            .map(str::parse)
+            .collect();
+        let (Ok(rating), Ok(date), flags, Ok(visits)) =
+            (v[1].parse::<f32>(), v[2].parse::<u64>(), v[3].to_string(), visits)
         else {
-            log_only(conf, &format!("Problem with fields: {}", row));
-            return self.contents.len();
+            return Err(bad_row());
         };
+        if !valid_rating(rating) {
+            return Err(bad_row());
+        }
+
+        // rows for paths that have gone are kept until clean prunes them
+        let pathbuf = canonical_string(&path).unwrap_or(path);
 
         // all okay, insert the row.
         self.contents.insert(
-            pathstring,
+            pathbuf,
             RasdfBaseData {
                 rating,
                 date,
                 flags,
+                visits,
             });
 
-        self.contents.len()
+        Ok(self.contents.len())
     }
 
-    pub fn from_data(conf: &Config, lines: &str) -> RasdfBase {
+    /// Build a database from the text of a datafile; Err if any row
+    /// can't be read, or the file is from a later version of rasdf.
+    pub fn from_data(conf: &Config, lines: &str) -> Result<RasdfBase, RasdfError> {
         let mut dbase = RasdfBase::new();
-        for line in lines.split('\n') {
-            dbase.add_line(conf, line);
+        let mut lines = lines.split('\n').zip(1..).peekable();
+
+        // files without a header line are v1
+        match lines.peek().and_then(|(line, _)| line.strip_prefix("#rasdf")) {
+            Some(header) => {
+                dbase.set_header(conf, header).map_err(|e| e.at_line(1))?;
+                lines.next();
+            }
+            None => dbase.version = 1,
+        };
+        if dbase.version > DATAFILE_VERSION {
+            return Err(RasdfError::NewerVersion {
+                path: conf.datafile.clone(),
+                version: dbase.version,
+            });
         }
-        dbase
+
+        for (line, n) in lines {
+            dbase.add_line(conf, line).map_err(|e| e.at_line(n))?;
+        }
+        Ok(dbase)
     }
 
-    pub fn from_file(conf: &Config) -> RasdfBase {
-        if let Ok(contents) = fs::read_to_string(&conf.datafile) {
-            // eprintln!("{}", &contents);
-            RasdfBase::from_data(conf, &contents)
-        } else {
-            RasdfBase::new()
+    /// Take the version and key=value fields from the rest of a
+    /// `#rasdf v<N> key=value...` header line.
+    fn set_header(&mut self, conf: &Config, header: &str) -> Result<(), RasdfError> {
+        let bad_header = || {
+            RasdfError::parse(&conf.datafile, format!("unrecognised header: #rasdf{}", header))
+        };
+        let mut fields = header.split_whitespace();
+        self.version = fields
+            .next()
+            .and_then(|v| v.strip_prefix('v'))
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(bad_header)?;
+
+        for field in fields {
+            // fields from later versions are left alone
+            match field.split_once('=') {
+                Some(("journal", value)) => self.journal = value.parse().map_err(|_| bad_header())?,
+                Some(("cleaned", value)) => self.cleaned = value.parse().map_err(|_| bad_header())?,
+                _ => (),
+            }
         }
+        Ok(())
     }
 
-    pub fn clean(&mut self, conf: &Config) -> bool {
-        if self.len() <= conf.maxlines {
-            log_only(conf, "Nothing to clean");
-            return false;
+    /// The key=value fields for the header when writing the database.
+    ///
+    /// Writing merges the journal, so it always starts a new generation;
+    /// records in the old journal are then never replayed over it again.
+    fn header_fields(&self) -> String {
+        match self.cleaned {
+            0 => format!("journal={}", self.journal + 1),
+            cleaned => format!("journal={} cleaned={}", self.journal + 1, cleaned),
+        }
+    }
+
+    /// Read the datafile in whichever format it was written, with the
+    /// journal of recent visits replayed over it;
+    /// a new database uses the format set by conf.backend.
+    ///
+    /// Only a missing datafile gives an empty database: one that can't
+    /// be read is an error, so that it is never written over.
+    pub fn from_file(conf: &Config) -> Result<RasdfBase, RasdfError> {
+        let mut dbase = match fs::read(&conf.datafile) {
+            Ok(contents) => storage::storage(storage::detect(&contents)).read(conf, &contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let mut dbase = RasdfBase::new();
+                dbase.backend = conf.backend;
+                dbase
+            }
+            Err(e) => return Err(RasdfError::io(&conf.datafile, e)),
         };
+        journal::replay(conf, &mut dbase)?;
+        Ok(dbase)
+    }
+
+    /// Read just the header of the datafile: a database with no rows.
+    pub fn header_from_file(conf: &Config) -> Result<RasdfBase, RasdfError> {
+        let mut head = Vec::new();
+        match fs::File::open(&conf.datafile) {
+            Ok(file) => file
+                .take(4096)
+                .read_to_end(&mut head)
+                .map_err(|e| RasdfError::io(&conf.datafile, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(RasdfBase::new()),
+            Err(e) => return Err(RasdfError::io(&conf.datafile, e)),
+        };
+        storage::storage(storage::detect(&head)).read_header(conf, &head)
+    }
 
-        // Adjust all ratings down by 10%
-        for rec in self.contents.values_mut() {
-            rec.rating *= 0.9;
+    /// Age the ratings by the time since the last clean, drop the rows
+    /// that fall below conf.drop_below, then the lowest rated rows over
+    /// conf.maxlines; true if anything changed.
+    pub fn clean(&mut self, conf: &Config) -> bool {
+        let pruned = self.prune(conf);
+        let aged = self.age(conf);
+
+        // drop the rows that have faded away
+        let before = self.len();
+        self.contents
+            .retain(|_, rec| rec.has_flag(PINNED) || rec.rating >= conf.drop_below);
+        let faded = before - self.len();
+        if faded > 0 {
+            log_only(conf, &format!("{} faded records dropped", faded));
         }
 
-        // A list of (path, rating) tuples sorted on rating
+        if self.len() <= conf.maxlines {
+            log_only(conf, "Nothing to truncate");
+            return pruned || aged || faded > 0;
+        };
+
+        // A list of (path, rating) tuples sorted on rating; pinned rows
+        // are not in it, so are never truncated
         let mut keys: Vec<_> = self
             .contents
-            .keys()
-            .map(|f| (f.to_string(), self.contents[f].rating))
+            .iter()
+            .filter(|(_, rec)| !rec.has_flag(PINNED))
+            .map(|(f, rec)| (f.clone(), rec.rating))
             .collect();
-        keys.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        keys.sort_by(|a, b| a.1.total_cmp(&b.1));
 
         // just keep the ones beyond MAXLINES
         // and remove them from the database.
-        keys.truncate(keys.len() - conf.maxlines);
+        keys.truncate(self.len() - conf.maxlines);
         let keys_truncated = keys.len();
         for f in keys.iter() {
             self.contents.remove(&f.0);
@@ -239,83 +498,115 @@ impl RasdfBase {
         true
     }
 
-    pub fn write_out(&self, conf: &Config) -> std::io::Result<()> {
-        let path = env::temp_dir().join("myfile.txt");
-        // println!("Writing to temp file {:?}", path);
-        let mut buffer = fs::File::create(&path)?;
+    /// Drop the rows for paths that no longer exist and have not been
+    /// visited for conf.missing_grace seconds, unless they are flagged
+    /// REMOVABLE; true if any were dropped.
+    fn prune(&mut self, conf: &Config) -> bool {
+        let before = self.len();
+        self.contents.retain(|path, rec| {
+            rec.has_flag(REMOVABLE)
+                || rec.has_flag(PINNED)
+                || rec.date.saturating_add(conf.missing_grace) > conf.current_time
+                || !is_missing(path)
+        });
+        let pruned = before - self.len();
+        if pruned > 0 {
+            log_only(conf, &format!("{} missing paths pruned", pruned));
+        }
+        pruned > 0
+    }
 
-        // write data out to temp file
-        for (key, value) in &self.contents {
-            buffer.write_all(
-                format!("{}|{}|{}|{}\n", key, value.rating, value.date, value.flags).as_bytes(),
-            )?;
+    /// Halve the ratings every conf.age_half_life seconds since the last
+    /// clean; true if they were aged. A datafile that has never been
+    /// cleaned is only marked as clean now.
+    fn age(&mut self, conf: &Config) -> bool {
+        let last = std::mem::replace(&mut self.cleaned, conf.current_time);
+        if last == 0 || last >= conf.current_time {
+            return last != self.cleaned;
         }
 
-        // and copy that back to proper place
-        // println!("Copying to {:?}", &conf.datafile);
-        fs::rename(&path, &conf.datafile).or_else(|_| fs::copy(&path, &conf.datafile).map(|_| ()))
+        let elapsed = conf.current_time - last;
+        let factor = 0.5f32.powf(elapsed as f32 / conf.age_half_life.max(1) as f32);
+        for rec in self.contents.values_mut().filter(|rec| !rec.has_flag(PINNED)) {
+            rec.rating *= factor;
+        }
+        log_only(conf, &format!("Ratings aged by {} over {}s", factor, elapsed));
+        true
     }
 
-    pub fn find_list(&self, conf: &Config) -> Vec<(&str, f32)> {
-        let mut v = Vec::<&str>::new();
+    /// Write the database out to conf.datafile.
+    ///
+    /// The rows are written to a uniquely named temp file in the same
+    /// folder, synced to disk and then renamed over the datafile, so a
+    /// reader only ever sees the old file or the complete new one.
+    pub fn write_out(&self, conf: &Config) -> Result<(), RasdfError> {
+        // never clobber a file written by a later version of rasdf
+        if self.version > DATAFILE_VERSION {
+            return Err(RasdfError::NewerVersion {
+                path: conf.datafile.clone(),
+                version: self.version,
+            });
+        }
+        if self.version < DATAFILE_VERSION {
+            log_only(
+                conf,
+                &format!("Migrating datafile from v{} to v{}", self.version, DATAFILE_VERSION),
+            );
+        }
 
-        // we want elements to look for either raw or lower-cased
-        let elements: Vec<String> = match conf.case_sensitive {
-            true => conf.arguments.clone(),
-            false => conf.arguments.iter().map(|s| s.to_lowercase()).collect(),
-        };
+        let (path, file) =
+            create_temp_file(&conf.datafile).map_err(|e| RasdfError::io(&conf.datafile, e))?;
 
-        // loop through all the paths in the data file
-        'paths: for path in self.contents.keys() {
-            // check if we're looking for dirs or folders (or both)
-            let p = PathBuf::from(&path);
-            if (!conf.find_dirs && p.is_dir()) || (!conf.find_files && p.is_file()) {
-                continue 'paths;
-            }
+        // write data out to temp file, tidying up if anything fails
+        let written = self.write_rows(file).and_then(|_| fs::rename(&path, &conf.datafile));
+        if let Err(e) = written {
+            let _ = fs::remove_file(&path);
+            return Err(RasdfError::io(&conf.datafile, e));
+        }
 
-            // make a mutable copy to turn into lowercase.
-            let mut pathstring = path.to_string();
-            // and an index to move along it
-            let mut start = 0usize;
+        // make sure the rename itself survives a crash
+        sync_parent_dir(&conf.datafile).map_err(|e| RasdfError::io(&conf.datafile, e))?;
 
-            // if case insensitive, convert everything to lowercase
-            if !conf.case_sensitive {
-                pathstring = pathstring.to_lowercase();
-            };
+        // the journal is merged in now
+        journal::remove(conf)
+    }
 
-            // for each element provided by the user
-            for element in elements.iter() {
+    fn write_rows(&self, file: fs::File) -> io::Result<()> {
+        let mut buffer = io::BufWriter::new(file);
+        storage::storage(self.backend).write(self, &mut buffer)?;
+        buffer.into_inner()?.sync_all()
+    }
 
-                // look through the pathstring and find it
-                if let Some(p) = pathstring[start..].find(element) {
-                    start += p + element.len();
-                } else {
-                    continue 'paths;
-                }
-            }
-            // only add the path if the last element was in the last segment.
-            if !conf.strict {
-                v.push(path);
-
-            } else {
-                // check out the last element, elements cannot be empty vec?
-                let last_element = elements.last().unwrap();
-                let last_segment_start = pathstring.rfind(MAIN_SEPARATOR).unwrap_or(0);
-                if pathstring[last_segment_start..].find(last_element).is_some() {
-                    v.push(path);
-                }
-            }
-        }
-        // collect each path into a (path , score) tuple
-        // Using unwrap is okay because the path is definitely in the database.
-        let mut result: Vec<(_, _)> = v
+    pub fn find_list(&self, conf: &Config) -> Vec<(&Path, f32)> {
+        self.find_list_in(conf, project_root(conf).as_deref())
+    }
+
+    /// As find_list, preferring paths in project, found by the caller
+    /// so that it looks for it only once.
+    fn find_list_in(&self, conf: &Config, project: Option<&Path>) -> Vec<(&Path, f32)> {
+        // main checks the terms before a find, so a bad pattern here
+        // just finds nothing
+        let Some(search) = Search::new(conf) else {
+            return Vec::new();
+        };
+
+        // loop through all the paths in the data file
+        let mut result: Vec<(&Path, f32)> = self
+            .contents
             .iter()
-            .map(|path| (*path, self.entry(path).unwrap().score(conf)))
+            .filter_map(|(path, data)| {
+                let quality = search.quality(conf, path, Some(data))?;
+                Some((path.as_path(), data.score(conf) * quality))
+            })
             .collect();
 
-        // Sort the results according to the score first then path
+        // Sort the results with pinned paths last (best), then those in
+        // the project, then according to the score, then path
         result.sort_by(|a, b| {
-            let mut ord = a.1.partial_cmp(&b.1).unwrap();
+            let mut ord = self.preferred(a.0, project).cmp(&self.preferred(b.0, project));
+            if ord == Ordering::Equal {
+                ord = a.1.total_cmp(&b.1);
+            }
             if ord == Ordering::Equal {
                 ord = a.0.cmp(b.0);
             }
@@ -324,29 +615,297 @@ impl RasdfBase {
         result
     }
 
-    pub fn find(&self, conf: &Config) -> Option<&str> {
-        let v = self.find_list(conf);
+    /// The folders above conf.from (or the current folder) whose own
+    /// name matches, whether or not they are in the database, nearest
+    /// (best) last; the score is how many segments deep each one is.
+    pub fn find_ancestors(&self, conf: &Config) -> Vec<(PathBuf, f32)> {
+        let Some(search) = Search::new(conf) else {
+            return Vec::new();
+        };
+        let from = conf.from.as_deref().unwrap_or_else(|| Path::new("."));
+        let Ok(from) = canonicalize(from) else {
+            return Vec::new();
+        };
+
+        let mut result: Vec<(PathBuf, f32)> = from
+            .ancestors()
+            .skip(1)
+            .filter(|path| {
+                // only the last segment, or every folder below a match
+                // would match too
+                let name = Path::new(path.file_name().unwrap_or(path.as_os_str()));
+                search.quality_of(conf, path, self.entry(path), name).is_some()
+            })
+            .map(|path| (path.to_path_buf(), path.components().count() as f32))
+            .collect();
+        result.reverse();
+        result
+    }
+
+    pub fn find(&self, conf: &Config) -> Option<&Path> {
+        let project = project_root(conf);
+        let v = self.find_list_in(conf, project.as_deref());
 
         // go through list of tuples and track the highest scoring
-        let mut ret: (Option<&str>, f32) = (None, f32::NAN);
+        let mut ret: (Option<&Path>, (bool, bool), f32) = (None, (false, false), f32::NAN);
         for t in v {
-            if ret.0.is_none() || ret.1.lt(&t.1) {
-                ret = (Some(t.0), t.1);
+            let preferred = self.preferred(t.0, project.as_deref());
+            if ret.0.is_none() || (ret.1, ret.2) < (preferred, t.1) {
+                ret = (Some(t.0), preferred, t.1);
             }
         }
         ret.0
     }
+
+    /// true if path, found other than by the terms (as a bookmark is),
+    /// still gets past the filters of a find: types, under, tags and
+    /// hidden rows.
+    pub fn allows(&self, conf: &Config, path: &Path) -> bool {
+        allowed(conf, under(conf).as_deref(), path, self.entry(path))
+    }
+
+    /// Whether path is pinned, and whether it is in project; either
+    /// puts it ahead of any score.
+    fn preferred(&self, path: &Path, project: Option<&Path>) -> (bool, bool) {
+        (
+            self.contents[path].has_flag(PINNED),
+            project.is_some_and(|project| path.starts_with(project)),
+        )
+    }
 }
 
-fn canonical_string(path: &str) -> Option<PathBuf> {
-    // return a Some(String) if path is a real path
+/// true if a rating read from a datafile could have been written by
+/// rasdf: a number, and not negative.
+pub(crate) fn valid_rating(rating: f32) -> bool {
+    rating.is_finite() && rating >= 0.0
+}
+
+/// With conf.project, the nearest folder at or above conf.from (or the
+/// current folder) with one of the project markers in it.
+pub fn project_root(conf: &Config) -> Option<PathBuf> {
+    if !conf.project {
+        return None;
+    }
+    let from = canonicalize(conf.from.as_deref().unwrap_or_else(|| Path::new("."))).ok()?;
+    from.ancestors()
+        .find(|dir| {
+            conf.project_markers
+                .iter()
+                .any(|marker| fs::symlink_metadata(dir.join(marker)).is_ok())
+        })
+        .map(Path::to_path_buf)
+}
+
+/// What a find looks for, worked out once for all the paths.
+struct Search {
+    terms: Vec<Term>,
+    /// true if any term ignores case, so paths need lower-casing too
+    folding: bool,
+    /// the folder results have to be in, if any
+    under: Option<PathBuf>,
+}
+
+impl Search {
+    /// None if the terms can't be read.
+    fn new(conf: &Config) -> Option<Search> {
+        let terms = query::terms(conf).ok()?;
+        Some(Search {
+            folding: terms.iter().any(|term| term.folded),
+            terms,
+            under: under(conf),
+        })
+    }
+
+    /// How well path (with data, if it has a row) is found, from 0 to
+    /// 1; None if it is not found at all.
+    fn quality(&self, conf: &Config, path: &Path, data: Option<&RasdfBaseData>) -> Option<f32> {
+        self.quality_of(conf, path, data, path)
+    }
+
+    /// As quality, but with the terms looked for in matched, which is
+    /// path or a part of it, rather than the whole of path.
+    fn quality_of(
+        &self,
+        conf: &Config,
+        path: &Path,
+        data: Option<&RasdfBaseData>,
+        matched: &Path,
+    ) -> Option<f32> {
+        if !allowed(conf, self.under.as_deref(), path, data) {
+            return None;
+        }
+
+        // the path as bytes, and lower-cased for terms that ignore case
+        let pathbytes = path_bytes(matched);
+        let folded = match self.folding {
+            true => Cow::Owned(fold_case(&pathbytes)),
+            false => Cow::Borrowed(&pathbytes[..]),
+        };
+
+        let terms = &self.terms;
+        if query::excluded(&pathbytes, &folded, terms)
+            || (conf.basename && !query::basename_matches(&pathbytes, &folded, terms))
+        {
+            return None;
+        }
+
+        // a fuzzy match counts for as much of the score as it is good
+        match conf.matching {
+            Matching::Fuzzy => {
+                fuzzy::quality(&pathbytes, &folded, terms, conf.strict, conf.segments)
+            }
+            _ => query::matches(&pathbytes, &folded, terms, conf.strict, conf.segments)
+                .then_some(1.0),
+        }
+    }
+}
+
+/// The folder conf.under, canonicalized, that results have to be in.
+fn under(conf: &Config) -> Option<PathBuf> {
+    conf.under
+        .as_ref()
+        .map(|dir| canonicalize(dir).unwrap_or_else(|_| dir.clone()))
+}
+
+/// true if path (with data, if it has a row) gets past the filters of a
+/// find, whatever the terms.
+fn allowed(conf: &Config, under: Option<&Path>, path: &Path, data: Option<&RasdfBaseData>) -> bool {
+    // hidden rows, and rows without every tag asked for, are never found
+    let has_flag = |flag| data.is_some_and(|data| data.has_flag(flag));
+    if has_flag(HIDDEN) || !conf.tags.chars().all(has_flag) {
+        return false;
+    }
+    if under.is_some_and(|under| !path.starts_with(under)) {
+        return false;
+    }
+
+    // check if we're looking for dirs or folders (or both);
+    // paths that are not there now (yet to be pruned, or on a
+    // removable drive) are never found
+    match fs::metadata(path) {
+        Ok(metadata) => {
+            (conf.find_dirs || !metadata.is_dir()) && (conf.find_files || !metadata.is_file())
+        }
+        Err(_) => false,
+    }
+}
+
+/// Create the folder holding the datafile, if it is not there yet.
+pub fn create_datafile_dir(conf: &Config) -> Result<(), RasdfError> {
+    match conf.datafile.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => {
+            fs::create_dir_all(dir).map_err(|e| RasdfError::io(dir, e))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Create a new temp file beside the datafile, named after it with the
+/// process id and a counter so that concurrent writers never share one.
+fn create_temp_file(datafile: &Path) -> io::Result<(PathBuf, fs::File)> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let filename = datafile
+        .file_name()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| String::from("rasdf.dat"));
+
+    loop {
+        let n = COUNTER.fetch_add(1, AtomicOrdering::Relaxed);
+        let path = datafile.with_file_name(format!(".{}.{}.{}.tmp", filename, process::id(), n));
+        match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            // left over from a dead process with the same pid; try the next name
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// fsync the folder holding path, so that a rename into it is durable.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => fs::File::open(dir)?.sync_all(),
+        _ => fs::File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// The canonical form of a path to add to the database, or None if it
+/// does not exist or matches one of the ignore patterns.
+fn path_to_add<P: AsRef<Path>>(conf: &Config, path: P) -> Result<Option<PathBuf>, RasdfError> {
+    let path = path.as_ref();
+    let pathbuf = match canonicalize(path) {
+        Ok(pathbuf) => pathbuf,
+        Err(e) => match e.kind() {
+            io::ErrorKind::NotFound
+            | io::ErrorKind::NotADirectory
+            | io::ErrorKind::InvalidFilename => return Ok(None),
+            _ => return Err(RasdfError::io(path, e)),
+        },
+    };
+    if conf.ignores(&pathbuf) {
+        log_only(conf, &format!("Ignoring path: {}", pathbuf.display()));
+        return Ok(None);
+    }
+    Ok(Some(pathbuf))
+}
+
+fn canonical_string<P: AsRef<Path>>(path: P) -> Option<PathBuf> {
+    // return a Some(PathBuf) if path is a real path
     // otherwise None
-    let mut pathstring = String::from(path);
-    if pathstring.starts_with('~') {
-        pathstring = pathstring.replacen('~', &home_dir()?, 1)
+    canonicalize(path).ok()
+}
+
+/// fs::canonicalize, with a leading `~` standing for the home folder.
+fn canonicalize<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
+    canonicalize_in(path, home_dir())
+}
+
+fn canonicalize_in<P: AsRef<Path>>(path: P, home: Option<String>) -> io::Result<PathBuf> {
+    let path = path.as_ref();
+    match path.strip_prefix("~") {
+        Ok(rest) => match home {
+            Some(home) => fs::canonicalize(PathBuf::from(home).join(rest)),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "no home folder")),
+        },
+        Err(_) => fs::canonicalize(path),
     }
+}
 
-    fs::canonicalize(&pathstring).ok()
+/// true if nothing exists at path; false if it exists or can't be told.
+fn is_missing(path: &Path) -> bool {
+    match fs::metadata(path) {
+        Err(e) => matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::NotADirectory),
+        Ok(_) => false,
+    }
+}
+
+/// Lower-case a path or search term: as text if it is valid UTF-8,
+/// otherwise just the ASCII letters. A letter whose lower case is a
+/// different length is left as it is, so that each byte of the result
+/// is at the same place as in the original; terms can then be looked
+/// for in either one.
+fn fold_case(bytes: &[u8]) -> Vec<u8> {
+    match std::str::from_utf8(bytes) {
+        Ok(s) => {
+            let mut folded = String::with_capacity(s.len());
+            for c in s.chars() {
+                let mut lower = c.to_lowercase();
+                match (lower.next(), lower.next()) {
+                    (Some(l), None) if l.len_utf8() == c.len_utf8() => folded.push(l),
+                    _ => folded.push(c),
+                }
+            }
+            folded.into_bytes()
+        }
+        Err(_) => bytes.to_ascii_lowercase(),
+    }
 }
 
 #[cfg(test)]
@@ -355,7 +914,16 @@ mod tests {
 
     #[test]
     fn test_canonical_string() {
-        assert!( canonical_string("/home/tim/tmp").is_some());
-        assert!( canonical_string("/home/user/tmp").is_none());
+        // a scratch home folder, with a tmp folder in it
+        let home = std::env::temp_dir().join(format!("rasdf-home-{}", process::id()));
+        fs::create_dir_all(home.join("tmp")).unwrap();
+        let home = fs::canonicalize(home).unwrap();
+        let in_home = |path| canonicalize_in(path, Some(home.to_string_lossy().into_owned())).ok();
+
+        assert_eq!( canonical_string(home.join("tmp")), Some(home.join("tmp")));
+        assert_eq!( in_home("~/tmp"), Some(home.join("tmp")));
+        assert!( in_home("~/missing").is_none());
+        assert!( canonical_string(home.join("missing")).is_none());
+        assert!( canonicalize_in("~/tmp", None).is_err());
     }
 }
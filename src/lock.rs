@@ -0,0 +1,61 @@
+//! Advisory locking around a read-modify-write of the datafile.
+//!
+//! The lock is an flock on a sidecar file, `<datafile>.lock`, so that
+//! the datafile itself can still be replaced by rename while it is held.
+
+use crate::config::Config;
+use crate::error::RasdfError;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// An exclusive lock on the datafile; released when dropped.
+pub struct DataLock {
+    file: fs::File,
+}
+
+impl DataLock {
+    /// Wait up to conf.lock_timeout milliseconds for the lock.
+    pub fn acquire(conf: &Config) -> Result<DataLock, RasdfError> {
+        let path = lock_path(&conf.datafile);
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(|e| RasdfError::io(&path, e))?;
+
+        let deadline = Instant::now() + Duration::from_millis(conf.lock_timeout);
+        loop {
+            match file.try_lock() {
+                Ok(()) => return Ok(DataLock { file }),
+                Err(fs::TryLockError::WouldBlock) if Instant::now() < deadline => {
+                    thread::sleep(Duration::from_millis(5));
+                }
+                Err(fs::TryLockError::WouldBlock) => {
+                    return Err(RasdfError::LockTimeout {
+                        path,
+                        timeout: conf.lock_timeout,
+                    })
+                }
+                Err(fs::TryLockError::Error(e)) => return Err(RasdfError::io(&path, e)),
+            }
+        }
+    }
+}
+
+impl Drop for DataLock {
+    fn drop(&mut self) {
+        // closing the file would release it anyway
+        let _ = self.file.unlock();
+    }
+}
+
+/// The sidecar lock file for a datafile: same name with `.lock` added.
+pub fn lock_path(datafile: &Path) -> PathBuf {
+    let mut name = datafile.as_os_str().to_os_string();
+    name.push(".lock");
+    PathBuf::from(name)
+}
@@ -20,11 +20,15 @@ pub fn log_only(conf: &Config, message: &str) {
     write_log(conf, message, false).expect("Failed writing to log file");
 }
 
-fn write_log(conf: &Config, message: &str, always: bool) -> std::io::Result<()> {
-    let d = UNIX_EPOCH + Duration::from_secs(conf.current_time);
+/// A UNIX-style datestamp as local date and time.
+pub fn format_date(date: u64) -> String {
+    let d = UNIX_EPOCH + Duration::from_secs(date);
     let datetime = DateTime::<Local>::from(d);
-    let timestamp_str = datetime.format("%Y-%m-%d %H:%M:%S");
-    let to_write = format!("{} rasdf: {}\n", timestamp_str, message);
+    datetime.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+fn write_log(conf: &Config, message: &str, always: bool) -> std::io::Result<()> {
+    let to_write = format!("{} rasdf: {}\n", format_date(conf.current_time), message);
 
     if let Some(logfile) = &conf.logging {
         fs::OpenOptions::new()
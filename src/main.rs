@@ -1,104 +1,367 @@
+use rasdf::bookmarks::Bookmarks;
+use rasdf::cli;
+use rasdf::config::{Backend, Config};
+use rasdf::encoding::path_bytes;
+use rasdf::error::RasdfError;
+use rasdf::lock::DataLock;
 use rasdf::logging::{log, log_only};
 
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+
+/// Print a path on its own line, byte for byte, so that the shell gets
+/// back exactly the folder name even if it is not valid UTF-8.
+fn print_path(path: &Path) {
+    let mut out = io::stdout().lock();
+    let _ = out
+        .write_all(&path_bytes(path))
+        .and_then(|_| out.write_all(b"\n"));
+}
+
+/// Exit status when nothing matched, or there was nothing to add or remove.
+const NO_MATCH: i32 = 1;
+
+/// Report an error, and exit with the status for its kind.
+fn fail(conf: &Config, context: &str, err: RasdfError) -> ! {
+    log(conf, &format!("{}: {}", context, err));
+    process::exit(err.exit_code());
+}
+
+/// Make sure the datafile has a folder to go in, or give up with an error.
+fn create_datafile_dir(conf: &Config) {
+    if let Err(e) = rasdf::create_datafile_dir(conf) {
+        fail(conf, "Failed to create data folder", e);
+    }
+}
+
+/// Take the datafile lock for a read-modify-write, or give up with an error.
+fn lock_datafile(conf: &Config) -> DataLock {
+    DataLock::acquire(conf).unwrap_or_else(|e| fail(conf, "Failed to lock data file", e))
+}
+
+/// Read the datafile, or give up with an error rather than carry on
+/// with an empty database.
+fn read_datafile(conf: &Config) -> rasdf::RasdfBase {
+    rasdf::RasdfBase::from_file(conf).unwrap_or_else(|e| fail(conf, "Failed to read data file", e))
+}
+
+/// Read the bookmarks, or give up with an error.
+fn read_bookmarks(conf: &Config) -> Bookmarks {
+    Bookmarks::from_file(conf).unwrap_or_else(|e| fail(conf, "Failed to read bookmarks", e))
+}
+
+/// Write the bookmarks, or give up with an error.
+fn write_bookmarks(conf: &Config, bookmarks: &Bookmarks) {
+    if let Err(e) = bookmarks.write_out(conf) {
+        fail(conf, "Failed to write bookmarks", e);
+    }
+}
+
+/// Give up with a usage error if the find terms can't be read.
+fn check_terms(conf: &Config) {
+    if let Err(e) = rasdf::query::terms(conf) {
+        fail(conf, &conf.executable, e);
+    }
+}
+
+/// Write the datafile, or give up with an error.
+fn write_datafile(conf: &Config, dbase: &rasdf::RasdfBase) {
+    if let Err(e) = dbase.write_out(conf) {
+        fail(conf, "Failed to write data file", e);
+    }
+}
+
 fn main() {
-    let conf = rasdf::config::Config::new();
-
-    let usage = "
-Commands: 
-    init
-    clean
-    add path [path...]
-    remove path
-    find segment [segment...]
-    find-all segment [segment..]
-    help
-    version
-
-Options:
-    -a, -d, -f     find all resources, directories, files
-    -D, -F, -R     use Date, Frecency, Rating
-    -s, -l         strict or lax last-segment restriction
-    -c, -i         case sensitive or insensitive
-";
+    let conf = match Config::new() {
+        Ok(conf) => conf,
+        Err(e) => {
+            let exe = std::env::args_os()
+                .next()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            eprintln!("{}: {}\n\n{}", exe, e, cli::usage(&exe));
+            process::exit(RasdfError::Usage(e).exit_code());
+        }
+    };
 
     // just to check what's going on...
     log_only(&conf, &format!("<{}>", rasdf::config::command_line()));
+    for warning in &conf.warnings {
+        log(&conf, warning);
+    }
 
     match conf.command.as_str() {
         "init" => {
-            let dbase = rasdf::RasdfBase::new();
-            if let Err(e) = dbase.write_out(&conf) {
-                log(&conf, &format!("Failed to write data file: {}", e));
-            } else {
-                log_only(&conf, "New database created.");
-            }
+            create_datafile_dir(&conf);
+            let _lock = lock_datafile(&conf);
+            write_datafile(&conf, &rasdf::RasdfBase::new());
+            log_only(&conf, "New database created.");
         }
 
         "clean" => {
-            let mut dbase = rasdf::RasdfBase::from_file(&conf);
-            if dbase.clean(&conf) {
-                if let Err(e) = dbase.write_out(&conf) {
-                    log(&conf, &format!("Failed to write data file: {}", e));
-                } else {
-                    log_only(
-                        &conf,
-                        &format!("Database cleaned; {} rows written.", dbase.len()),
-                    );
-                }
+            let _lock = lock_datafile(&conf);
+            let mut dbase = read_datafile(&conf);
+            // clean always merges the journal into the datafile
+            let journalled = rasdf::journal::journal_path(&conf.datafile).exists();
+            if dbase.clean(&conf) || journalled {
+                write_datafile(&conf, &dbase);
+                log_only(
+                    &conf,
+                    &format!("Database cleaned; {} rows written.", dbase.len()),
+                );
             } else {
                 log_only(&conf, "Nothing to clean.");
             }
         }
 
         "add" => {
-            let mut dbase = rasdf::RasdfBase::from_file(&conf);
-
             // check that all arguments are not blacklisted
             for arg in &conf.arguments {
-                if conf.cmd_blacklist.iter().any(|&x| x == arg) {
+                if conf.cmd_blacklist.iter().any(|x| arg == x.as_str()) {
                     // if conf.cmd_blacklist.iter().find(|&x| x == arg).is_some() {
-                    log_only(&conf, &format!("Blacklisted command <{}>", arg));
-                    return;
+                    log_only(&conf, &format!("Blacklisted command <{}>", arg.to_string_lossy()));
+                    process::exit(NO_MATCH);
                 }
             }
 
-            for arg in &conf.arguments {
-                dbase.add_path(&conf, arg);
-            }
+            create_datafile_dir(&conf);
+            let _lock = lock_datafile(&conf);
 
-            if let Err(e) = dbase.write_out(&conf) {
-                log(&conf, &format!("Failed to write data file: {}", e));
-            }; // don't log every addition!
+            // just append to the journal, unless it's time to merge it
+            match rasdf::journal::append(&conf, &conf.arguments) {
+                Ok((0, _)) => {
+                    log_only(&conf, "No paths to add.");
+                    process::exit(NO_MATCH);
+                }
+                Ok((_, size)) if size > conf.journal_max => {
+                    write_datafile(&conf, &read_datafile(&conf));
+                }
+                Ok(_) => (), // don't log every addition!
+                Err(e) => fail(&conf, "Failed to write journal", e),
+            }
         }
 
         "remove" => {
-            let mut dbase = rasdf::RasdfBase::from_file(&conf);
-            dbase.remove(&conf);
-            if let Err(e) = dbase.write_out(&conf) {
-                log(&conf, &format!("Failed to write data file: {}", e));
+            let Some(path) = conf.arguments.first() else {
+                let e = RasdfError::Usage(String::from("remove needs a path"));
+                fail(&conf, &conf.executable, e);
+            };
+
+            let _lock = lock_datafile(&conf);
+            let mut dbase = read_datafile(&conf);
+            if !dbase.remove(path) {
+                log(
+                    &conf,
+                    &format!("Could not find row to remove: {}", Path::new(path).display()),
+                );
+                process::exit(NO_MATCH);
+            }
+            write_datafile(&conf, &dbase);
+        }
+
+        "convert" => {
+            let backend = conf
+                .arguments
+                .first()
+                .and_then(|arg| arg.to_str())
+                .and_then(Backend::from);
+            let Some(backend) = backend else {
+                let e = RasdfError::Usage(String::from("convert needs a format: text or binary"));
+                fail(&conf, &conf.executable, e);
             };
+
+            let _lock = lock_datafile(&conf);
+            let mut dbase = read_datafile(&conf);
+            dbase.set_backend(backend);
+            write_datafile(&conf, &dbase);
+            log_only(&conf, &format!("Data file converted to {:?}.", backend));
         }
 
         "find-all" => {
-            let dbase = rasdf::RasdfBase::from_file(&conf);
+            check_terms(&conf);
+            let dbase = read_datafile(&conf);
             // eprintln!("Read {} lines.", dbase.len());
 
-            let rets = dbase.find_list(&conf);
+            // the folders above are not all in the database, so are owned
+            let ancestors;
+            let rets: Vec<(&Path, f32)> = match rasdf::query::ancestor_search(&conf) {
+                true => {
+                    ancestors = dbase.find_ancestors(&conf);
+                    ancestors.iter().map(|(path, score)| (path.as_path(), *score)).collect()
+                }
+                false => dbase.find_list(&conf),
+            };
             for ret in rets.iter() {
-                println!("{:6.4} {}", ret.1, ret.0);
+                print!("{:6.4} ", ret.1);
+                print_path(ret.0);
+            }
+            if rets.is_empty() {
+                process::exit(NO_MATCH);
             }
         }
 
         "find" => {
-            let dbase = rasdf::RasdfBase::from_file(&conf);
+            check_terms(&conf);
+            let dbase = read_datafile(&conf);
             // eprintln!("Read {} lines.", dbase.len());
 
-            if let Some(ret) = dbase.find(&conf) {
-                println!("{}", ret);
+            // a bookmark name wins over anything the scores would pick,
+            // if it gets past the same filters
+            let bookmarks = read_bookmarks(&conf);
+            if let Some(path) = bookmarks.find(&conf).filter(|path| dbase.allows(&conf, path)) {
+                print_path(path);
+                return;
+            }
+
+            if rasdf::query::ancestor_search(&conf) {
+                match dbase.find_ancestors(&conf).pop() {
+                    Some((ret, _)) => print_path(&ret),
+                    None => process::exit(NO_MATCH),
+                };
+                return;
+            }
+
+            match dbase.find(&conf) {
+                Some(ret) => print_path(ret),
+                None => process::exit(NO_MATCH),
             };
         }
 
-        "help" => println!("{} COMMAND [OPTIONS...]\n{}", conf.executable, usage),
+        "flag" => {
+            let (Some(action), Some(path), Some(flags), None) = (
+                conf.arguments.first().and_then(|arg| arg.to_str()),
+                conf.arguments.get(1),
+                conf.arguments.get(2).and_then(|arg| arg.to_str()),
+                conf.arguments.get(3),
+            ) else {
+                let e = RasdfError::Usage(String::from("flag needs add or remove, a path and flags"));
+                fail(&conf, &conf.executable, e);
+            };
+            let add = match action {
+                "add" => true,
+                "remove" => false,
+                _ => {
+                    let e = RasdfError::Usage(format!("flag add or flag remove, not flag {}", action));
+                    fail(&conf, &conf.executable, e);
+                }
+            };
+            if flags.is_empty() || !flags.chars().all(rasdf::is_flag) {
+                let e = RasdfError::Usage(format!("flags must be letters or digits, not {}", flags));
+                fail(&conf, &conf.executable, e);
+            }
+
+            let _lock = lock_datafile(&conf);
+            let mut dbase = read_datafile(&conf);
+            if !dbase.set_flags(path, flags, add) {
+                log(
+                    &conf,
+                    &format!("Path is not in the database: {}", Path::new(path).display()),
+                );
+                process::exit(NO_MATCH);
+            }
+            write_datafile(&conf, &dbase);
+        }
+
+        "history" => {
+            let Some(path) = conf.arguments.first() else {
+                let e = RasdfError::Usage(String::from("history needs a path"));
+                fail(&conf, &conf.executable, e);
+            };
+
+            let dbase = read_datafile(&conf);
+            let Some((path, data)) = dbase.lookup(path) else {
+                log(
+                    &conf,
+                    &format!("Path is not in the database: {}", Path::new(path).display()),
+                );
+                process::exit(NO_MATCH);
+            };
+            print_path(path);
+            // rows from before visits were recorded only have the last one
+            let visits = match data.visits.is_empty() {
+                true => std::slice::from_ref(&data.date),
+                false => &data.visits[..],
+            };
+            for &date in visits {
+                println!("{}", rasdf::logging::format_date(date));
+            }
+        }
+
+        "bookmark" => {
+            let (Some(name), Some(path), None) = (
+                conf.arguments.first().and_then(|arg| arg.to_str()),
+                conf.arguments.get(1),
+                conf.arguments.get(2),
+            ) else {
+                let e = RasdfError::Usage(String::from("bookmark needs a name and a path"));
+                fail(&conf, &conf.executable, e);
+            };
+            if name.is_empty() {
+                let e = RasdfError::Usage(String::from("bookmark names can't be empty"));
+                fail(&conf, &conf.executable, e);
+            }
+
+            create_datafile_dir(&conf);
+            let _lock = lock_datafile(&conf);
+            let mut bookmarks = read_bookmarks(&conf);
+            if let Err(e) = bookmarks.set(name, path) {
+                log(
+                    &conf,
+                    &format!("Can't bookmark {}: {}", Path::new(path).display(), e),
+                );
+                process::exit(NO_MATCH);
+            }
+            write_bookmarks(&conf, &bookmarks);
+        }
+
+        "unbookmark" => {
+            let Some(name) = conf.arguments.first() else {
+                let e = RasdfError::Usage(String::from("unbookmark needs a name"));
+                fail(&conf, &conf.executable, e);
+            };
+
+            let _lock = lock_datafile(&conf);
+            let mut bookmarks = read_bookmarks(&conf);
+            if !name.to_str().is_some_and(|name| bookmarks.remove(name)) {
+                log(
+                    &conf,
+                    &format!("No bookmark called {}", name.to_string_lossy()),
+                );
+                process::exit(NO_MATCH);
+            }
+            write_bookmarks(&conf, &bookmarks);
+        }
+
+        "bookmarks" => {
+            for (name, path) in read_bookmarks(&conf).iter() {
+                print!("{} ", name);
+                print_path(path);
+            }
+        }
+
+        "config" => {
+            if let Some(path) = rasdf::config::config_path() {
+                println!("# settings file: {}", path.display());
+            }
+            print!("{}", conf.describe());
+        }
+
+        "help" => match conf.arguments.first() {
+            None => print!("{}", cli::usage(&conf.executable)),
+            Some(name) => match cli::command(&name.to_string_lossy()) {
+                Some(cmd) => print!("{}", cli::command_usage(&conf.executable, cmd)),
+                None => {
+                    eprintln!(
+                        "{}: not a valid command <{}>\n\n{}",
+                        conf.executable,
+                        name.to_string_lossy(),
+                        cli::usage(&conf.executable)
+                    );
+                    process::exit(2);
+                }
+            },
+        },
 
         "version" => println!(
             "{} version {}\nData file: {}",
@@ -107,11 +370,10 @@ Options:
             conf.datafile.display()
         ),
 
+        // no command given; parse_args has already refused unknown ones
         &_ => {
-            eprintln!(
-                "{}: not a valid command <{}>\n{}",
-                conf.executable, conf.command, usage
-            );
+            eprint!("{}", cli::usage(&conf.executable));
+            process::exit(2);
         }
     }
 }
@@ -0,0 +1,508 @@
+//! Search terms: each find argument, read as what to look for in a path.
+//!
+//! A term starting with `!` excludes every path it is found in. In the
+//! literal and fuzzy modes, a term starting with `^` has to start a
+//! segment and one ending with `$` has to end one; a `/` in a term is
+//! just the separator, so `foo / bar` finds bar in a later segment than
+//! foo. With `matching = "regex"` each term is a regular expression,
+//! and with `matching = "glob"` each is a glob pattern for one or more
+//! whole segments. With `segments`, each term has to be in a later
+//! segment than the one before, and `anchor_first` holds the first
+//! term to the start of one. In the literal and fuzzy modes, a first
+//! term starting with `..` looks among the folders above the current
+//! one instead of the database.
+
+use crate::config::{Case, Config, Matching};
+use crate::encoding::path_bytes;
+use crate::error::RasdfError;
+use crate::fold_case;
+use crate::glob::glob_match;
+
+use regex::bytes::{Regex, RegexBuilder};
+use regex_syntax::ast::{self, Ast, ClassSetItem};
+
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, MAIN_SEPARATOR};
+
+const SEP: u8 = MAIN_SEPARATOR as u8;
+
+/// How a term is matched
+pub enum Pattern {
+    /// the bytes as they are, perhaps held to the start or end of a segment
+    Literal { at_start: bool, at_end: bool },
+    /// a regular expression, which is given the path as it is
+    Regex(Regex),
+    /// a glob pattern, matched against whole segments
+    Glob,
+}
+
+/// A find argument, as the bytes to look for in a path.
+pub struct Term {
+    pub bytes: Vec<u8>,
+    /// true if the bytes are lower-cased, to be looked for in the
+    /// lower-cased path
+    pub folded: bool,
+    /// true if a path with this term in it is never found
+    pub exclude: bool,
+    pub pattern: Pattern,
+}
+
+/// The terms for conf.arguments; Err if a pattern can't be read.
+pub fn terms(conf: &Config) -> Result<Vec<Term>, RasdfError> {
+    let mut terms = conf
+        .arguments
+        .iter()
+        .enumerate()
+        .map(|(i, arg)| match (i, ancestor_term(conf, arg)) {
+            (0, Some(rest)) => Term::new(conf, rest),
+            _ => Term::new(conf, arg),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if conf.anchor_first {
+        let first = terms.iter_mut().find(|term| !term.exclude);
+        if let Some(Term {
+            pattern: Pattern::Literal { at_start, .. },
+            ..
+        }) = first
+        {
+            *at_start = true;
+        }
+    }
+    Ok(terms)
+}
+
+/// true if find looks among the folders above the current one (or
+/// conf.from), rather than in the database: if conf.ancestors is set,
+/// or the first term starts with `..`, as in `..proj`.
+pub fn ancestor_search(conf: &Config) -> bool {
+    conf.ancestors
+        || conf
+            .arguments
+            .first()
+            .and_then(|arg| ancestor_term(conf, arg))
+            .is_some()
+}
+
+/// The rest of a term that starts with `..`, if there is any rest. Only
+/// in the literal and fuzzy modes: in a regex or glob, `..` is part of
+/// the pattern.
+fn ancestor_term<'a>(conf: &Config, arg: &'a OsString) -> Option<&'a OsStr> {
+    if !matches!(conf.matching, Matching::Literal | Matching::Fuzzy) {
+        return None;
+    }
+    match arg.to_str()?.strip_prefix("..") {
+        Some(rest) if !rest.is_empty() => Some(OsStr::new(rest)),
+        _ => None,
+    }
+}
+
+/// true if a regex has an upper case letter it matches as it is, in a
+/// literal or a class; false if it can't be read, which building it
+/// will report.
+fn regex_has_upper(bytes: &[u8]) -> bool {
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(_) => return false,
+    };
+    match ast::parse::Parser::new().parse(text) {
+        Ok(regex) => ast::visit(&regex, UpperLiterals(false)).unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+/// Looks through a regex for upper case literals.
+struct UpperLiterals(bool);
+
+impl ast::Visitor for UpperLiterals {
+    type Output = bool;
+    type Err = ();
+
+    fn finish(self) -> Result<bool, ()> {
+        Ok(self.0)
+    }
+
+    fn visit_pre(&mut self, regex: &Ast) -> Result<(), ()> {
+        if let Ast::Literal(literal) = regex {
+            self.0 |= literal.c.is_uppercase();
+        }
+        Ok(())
+    }
+
+    fn visit_class_set_item_pre(&mut self, item: &ClassSetItem) -> Result<(), ()> {
+        match item {
+            ClassSetItem::Literal(literal) => self.0 |= literal.c.is_uppercase(),
+            ClassSetItem::Range(range) => {
+                self.0 |= range.start.c.is_uppercase() || range.end.c.is_uppercase()
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl Term {
+    pub fn new<S: AsRef<OsStr>>(conf: &Config, arg: S) -> Result<Term, RasdfError> {
+        let arg_bytes = path_bytes(Path::new(&arg));
+        let mut bytes: &[u8] = &arg_bytes;
+        let exclude = bytes.len() > 1 && bytes[0] == b'!';
+        if exclude {
+            bytes = &bytes[1..];
+        }
+        // each term decides for itself with smart case; in a regex only
+        // its literal letters count, not escapes such as \S or \W
+        let sensitive = match (conf.case, conf.matching) {
+            (Case::Smart, Matching::Regex) => regex_has_upper(bytes),
+            _ => conf.case.is_sensitive(&String::from_utf8_lossy(bytes)),
+        };
+
+        let pattern = match conf.matching {
+            Matching::Literal | Matching::Fuzzy => {
+                let at_start = bytes.len() > 1 && bytes[0] == b'^';
+                if at_start {
+                    bytes = &bytes[1..];
+                }
+                let at_end = bytes.len() > 1 && bytes[bytes.len() - 1] == b'$';
+                if at_end {
+                    bytes = &bytes[..bytes.len() - 1];
+                }
+                Pattern::Literal { at_start, at_end }
+            }
+            Matching::Regex => {
+                let text = std::str::from_utf8(bytes).map_err(|_| {
+                    RasdfError::Usage(format!(
+                        "regex is not valid text: {}",
+                        arg.as_ref().to_string_lossy()
+                    ))
+                })?;
+                let regex = RegexBuilder::new(text)
+                    .case_insensitive(!sensitive)
+                    .build()
+                    .map_err(|e| RasdfError::Usage(format!("bad regex {}: {}", text, e)))?;
+                // the regex does its own case folding
+                return Ok(Term {
+                    bytes: bytes.to_vec(),
+                    folded: false,
+                    exclude,
+                    pattern: Pattern::Regex(regex),
+                });
+            }
+            Matching::Glob => Pattern::Glob,
+        };
+        Ok(Term {
+            bytes: match sensitive {
+                true => bytes.to_vec(),
+                false => fold_case(bytes),
+            },
+            folded: !sensitive,
+            exclude,
+            pattern,
+        })
+    }
+
+    /// Whichever of the path or the lower-cased path to look in.
+    pub fn haystack<'a>(&self, pathbytes: &'a [u8], folded: &'a [u8]) -> &'a [u8] {
+        match self.folded {
+            true => folded,
+            false => pathbytes,
+        }
+    }
+
+    /// true if the term has to start a segment
+    pub fn at_start(&self) -> bool {
+        matches!(self.pattern, Pattern::Literal { at_start: true, .. })
+    }
+
+    /// true if the term has to end a segment
+    pub fn at_end(&self) -> bool {
+        matches!(self.pattern, Pattern::Literal { at_end: true, .. })
+    }
+
+    /// Where the first match of the term that starts at or after from
+    /// ends, if there is one.
+    pub fn find(&self, pathbytes: &[u8], folded: &[u8], from: usize) -> Option<usize> {
+        let haystack = self.haystack(pathbytes, folded);
+        match &self.pattern {
+            Pattern::Literal { at_start, at_end } => {
+                let mut from = from;
+                while from <= haystack.len() {
+                    let start = from + find_bytes(&haystack[from..], &self.bytes)?;
+                    let end = start + self.bytes.len();
+                    if (!at_start || segment_start(haystack, start))
+                        && (!at_end || segment_end(haystack, end))
+                    {
+                        return Some(end);
+                    }
+                    from = start + 1;
+                }
+                None
+            }
+            Pattern::Regex(regex) => regex.find_at(haystack, from).map(|m| m.end()),
+            // the first segment it can start, and as few segments as will do
+            Pattern::Glob => (from..haystack.len())
+                .filter(|&start| segment_start(haystack, start) && haystack[start] != SEP)
+                .find_map(|start| {
+                    (start + 1..=haystack.len())
+                        .filter(|&end| segment_end(haystack, end))
+                        .find(|&end| glob_match(&self.bytes, &haystack[start..end]))
+                }),
+        }
+    }
+}
+
+/// true if the terms that are not exclusions are in the path, in order;
+/// if strict, the last one must also be in the last segment, and if
+/// segments, each must start in a later segment than the one before
+/// ended. folded is the path lower-cased.
+pub fn matches(
+    pathbytes: &[u8],
+    folded: &[u8],
+    terms: &[Term],
+    strict: bool,
+    segments: bool,
+) -> bool {
+    // an index to move along the path, until it runs out of segments
+    let mut start = Some(0usize);
+    let mut last_term = None;
+
+    for term in terms.iter().filter(|term| !term.exclude) {
+        // look through the path and find it
+        match start.and_then(|start| term.find(pathbytes, folded, start)) {
+            Some(end) if segments => start = next_segment(pathbytes, end),
+            Some(end) => start = Some(end),
+            None => return false,
+        }
+        last_term = Some(term);
+    }
+    // only a match if the last term was in the last segment.
+    match (strict, last_term) {
+        (true, Some(last_term)) => {
+            let last_segment_start = pathbytes.iter().rposition(|&b| b == SEP).unwrap_or(0);
+            last_term
+                .find(pathbytes, folded, last_segment_start)
+                .is_some()
+        }
+        _ => true,
+    }
+}
+
+/// true if the last term that is not an exclusion is the whole of the
+/// last segment of the path.
+pub fn basename_matches(pathbytes: &[u8], folded: &[u8], terms: &[Term]) -> bool {
+    let Some(term) = terms.iter().rev().find(|term| !term.exclude) else {
+        return true;
+    };
+    let haystack = term.haystack(pathbytes, folded);
+    let start = haystack
+        .iter()
+        .rposition(|&b| b == SEP)
+        .map_or(0, |i| i + 1);
+    let name = &haystack[start..];
+    match &term.pattern {
+        Pattern::Literal { .. } => name == &term.bytes[..],
+        Pattern::Regex(regex) => regex
+            .find(name)
+            .is_some_and(|m| m.start() == 0 && m.end() == name.len()),
+        Pattern::Glob => glob_match(&term.bytes, name),
+    }
+}
+
+/// true if any of the exclusions is in the path.
+pub fn excluded(pathbytes: &[u8], folded: &[u8], terms: &[Term]) -> bool {
+    terms
+        .iter()
+        .filter(|term| term.exclude)
+        .any(|term| term.find(pathbytes, folded, 0).is_some())
+}
+
+/// true if a segment starts at i
+pub fn segment_start(path: &[u8], i: usize) -> bool {
+    i == 0 || path[i - 1] == SEP
+}
+
+/// true if a segment ends at i
+pub fn segment_end(path: &[u8], i: usize) -> bool {
+    i == path.len() || path[i] == SEP
+}
+
+/// Where the first segment that starts at or after i starts, if any
+fn next_segment(path: &[u8], i: usize) -> Option<usize> {
+    match i > 0 && path[i - 1] == SEP {
+        true => Some(i),
+        false => path[i..].iter().position(|&b| b == SEP).map(|p| i + p + 1),
+    }
+}
+
+/// Byte offset of the first occurrence of needle in haystack.
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(matching: Matching, arg: &str) -> Term {
+        let conf = Config {
+            matching,
+            case: crate::config::Case::Sensitive,
+            ..Config::defaults()
+        };
+        Term::new(&conf, arg).unwrap()
+    }
+
+    fn found(matching: Matching, args: &[&str], path: &str) -> bool {
+        let terms: Vec<Term> = args.iter().map(|arg| term(matching, arg)).collect();
+        let path = path.as_bytes();
+        !excluded(path, path, &terms) && matches(path, path, &terms, false, false)
+    }
+
+    #[test]
+    fn test_anchors() {
+        let path = "/home/me/projects/rasdf";
+        assert!(found(Matching::Literal, &["^proj"], path));
+        assert!(!found(Matching::Literal, &["^roj"], path));
+        assert!(found(Matching::Literal, &["ects$"], path));
+        assert!(!found(Matching::Literal, &["ject$"], path));
+        assert!(found(Matching::Literal, &["^rasdf$"], path));
+        assert!(found(Matching::Literal, &["^me$", "^ras"], path));
+        // a bare ^ or $ is just the character
+        assert!(!found(Matching::Literal, &["^"], path));
+        assert!(found(Matching::Literal, &["^", "$"], "/a^b$"));
+    }
+
+    #[test]
+    fn test_separators() {
+        assert!(found(
+            Matching::Literal,
+            &["me", "/", "ras"],
+            "/home/me/rasdf"
+        ));
+        assert!(!found(Matching::Literal, &["ho", "/", "me"], "/home"));
+        assert!(found(Matching::Literal, &["me/ras"], "/home/me/rasdf"));
+    }
+
+    #[test]
+    fn test_segments() {
+        let matched = |args: &[&str], segments| {
+            let terms: Vec<Term> = args
+                .iter()
+                .map(|arg| term(Matching::Literal, arg))
+                .collect();
+            let path = b"/work/mono/my-files/file.txt";
+            matches(path, path, &terms, false, segments)
+        };
+        assert!(matched(&["my", "ile", "tx"], false));
+        assert!(!matched(&["my", "ile", "tx"], true));
+        assert!(matched(&["mon", "my", "tx"], true));
+        assert!(matched(&["mono/", "my"], true));
+        assert!(!matched(&["txt", "txt"], true));
+        assert!(!matched(&["mon", "mono"], true));
+    }
+
+    #[test]
+    fn test_exclude() {
+        assert!(found(Matching::Literal, &["src", "!target"], "/p/src"));
+        assert!(!found(
+            Matching::Literal,
+            &["src", "!target"],
+            "/p/target/src"
+        ));
+        assert!(!found(Matching::Literal, &["!^tar"], "/p/target/src"));
+        assert!(found(Matching::Literal, &["!^arg"], "/p/target/src"));
+        // a bare ! is just the character
+        assert!(found(Matching::Literal, &["!"], "/p/wow!"));
+    }
+
+    #[test]
+    fn test_regex() {
+        assert!(found(
+            Matching::Regex,
+            &["pro[a-z]+s/r"],
+            "/home/projects/rasdf"
+        ));
+        assert!(found(
+            Matching::Regex,
+            &["^/home", "df$"],
+            "/home/projects/rasdf"
+        ));
+        assert!(!found(
+            Matching::Regex,
+            &["^/projects"],
+            "/home/projects/rasdf"
+        ));
+        assert!(!found(
+            Matching::Regex,
+            &["proj", "!r.s"],
+            "/home/projects/rasdf"
+        ));
+
+        let conf = Config {
+            matching: Matching::Regex,
+            ..Config::defaults()
+        };
+        assert!(Term::new(&conf, "(unclosed").is_err());
+    }
+
+    #[test]
+    fn test_regex_smart_case() {
+        let conf = Config {
+            matching: Matching::Regex,
+            case: Case::Smart,
+            ..Config::defaults()
+        };
+        let folded = |arg: &str| match Term::new(&conf, arg).unwrap().pattern {
+            Pattern::Regex(regex) => regex.is_match(b"/HOME/ab12x y"),
+            _ => unreachable!(),
+        };
+        // escapes such as \S, \W and \A aren't upper case letters
+        assert!(folded(r"\A/home/\w+\d\S\W"));
+        assert!(folded(r"^/[a-z]+/"));
+        assert!(!folded(r"^/Home"));
+        assert!(!folded(r"^/[A-Z]ome"));
+        assert!(!folded(r"\A/Ho"));
+    }
+
+    #[test]
+    fn test_ancestor_prefix() {
+        let search = |matching, arg: &str| {
+            let conf = Config {
+                matching,
+                arguments: vec![OsString::from(arg)],
+                ..Config::defaults()
+            };
+            ancestor_search(&conf)
+        };
+        assert!(search(Matching::Literal, "..proj"));
+        assert!(search(Matching::Fuzzy, "..proj"));
+        assert!(!search(Matching::Literal, ".."));
+        // in a regex or glob, .. is part of the pattern
+        assert!(!search(Matching::Regex, "..proj"));
+        assert!(!search(Matching::Glob, "..proj"));
+        assert!(found(Matching::Regex, &["..proj"], "/home/myproj"));
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(found(Matching::Glob, &["pro*"], "/home/projects/rasdf"));
+        assert!(!found(Matching::Glob, &["roj*"], "/home/projects/rasdf"));
+        assert!(found(
+            Matching::Glob,
+            &["home", "r?sdf"],
+            "/home/projects/rasdf"
+        ));
+        assert!(!found(
+            Matching::Glob,
+            &["r?sdf", "home"],
+            "/home/projects/rasdf"
+        ));
+        assert!(found(
+            Matching::Glob,
+            &["projects/*"],
+            "/home/projects/rasdf"
+        ));
+        assert!(found(Matching::Glob, &["h**s"], "/home/projects/rasdf"));
+    }
+}
@@ -0,0 +1,226 @@
+//! Storage backends for the datafile.
+//!
+//! A backend turns the raw bytes of a datafile into a RasdfBase and
+//! back again; RasdfBase::from_file and write_out deal with reading,
+//! locking and atomically replacing the file itself.
+
+use crate::config::{Backend, Config};
+use crate::encoding::{encode_path, path_bytes, path_from_bytes};
+use crate::error::RasdfError;
+use crate::{valid_rating, RasdfBase, RasdfBaseData, DATAFILE_VERSION};
+
+use std::convert::TryInto;
+use std::io::{self, Write};
+
+/// First bytes of a binary datafile.
+const BINARY_MAGIC: &[u8; 8] = b"RASDFBIN";
+
+pub trait Storage {
+    /// Build a database from the contents of a datafile; Err if any of
+    /// it can't be read.
+    fn read(&self, conf: &Config, data: &[u8]) -> Result<RasdfBase, RasdfError>;
+
+    /// Read only the header from the start of a datafile; no rows.
+    fn read_header(&self, conf: &Config, data: &[u8]) -> Result<RasdfBase, RasdfError>;
+
+    /// Write every row of the database to out.
+    fn write(&self, dbase: &RasdfBase, out: &mut dyn Write) -> io::Result<()>;
+}
+
+/// The backend for a given choice of format.
+pub fn storage(backend: Backend) -> &'static dyn Storage {
+    match backend {
+        Backend::Text => &TextStorage,
+        Backend::Binary => &BinaryStorage,
+    }
+}
+
+/// Which backend wrote a datafile, judged by its first bytes.
+pub fn detect(data: &[u8]) -> Backend {
+    if data.starts_with(BINARY_MAGIC) {
+        Backend::Binary
+    } else {
+        Backend::Text
+    }
+}
+
+/// The original format: a `#rasdf vN` header then `path|rating|date|flags` rows.
+pub struct TextStorage;
+
+impl Storage for TextStorage {
+    fn read(&self, conf: &Config, data: &[u8]) -> Result<RasdfBase, RasdfError> {
+        RasdfBase::from_data(conf, &String::from_utf8_lossy(data))
+    }
+
+    fn read_header(&self, conf: &Config, data: &[u8]) -> Result<RasdfBase, RasdfError> {
+        let first_line = data.split(|&b| b == b'\n').next().unwrap_or_default();
+        if first_line.starts_with(b"#rasdf") {
+            RasdfBase::from_data(conf, &String::from_utf8_lossy(first_line))
+        } else {
+            RasdfBase::from_data(conf, "")
+        }
+    }
+
+    fn write(&self, dbase: &RasdfBase, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(format!("#rasdf v{} {}\n", DATAFILE_VERSION, dbase.header_fields()).as_bytes())?;
+        for (key, value) in &dbase.contents {
+            let mut row = format!(
+                "{}|{}|{}|{}",
+                encode_path(key),
+                value.rating,
+                value.date,
+                value.flags
+            );
+            // rows with no visits recorded are left as they were in v4
+            if !value.visits.is_empty() {
+                let visits: Vec<String> = value.visits.iter().map(u64::to_string).collect();
+                row.push('|');
+                row.push_str(&visits.join(","));
+            }
+            row.push('\n');
+            out.write_all(row.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// A compact format for large histories; nothing to parse but lengths.
+///
+/// After the magic bytes, a u32 format version, a u32 length and the
+/// key=value header fields as text, and a u32 row count (v3 files have
+/// no header fields); then for each row a u32 length and the raw path bytes, an f32
+/// rating, a u64 date, a u32 length and the flags, and (from v5) a u32
+/// count and that many u64 dates of recent visits. All numbers are
+/// little-endian. Paths are stored already canonical, so are not
+/// checked against the filesystem again on reading.
+pub struct BinaryStorage;
+
+impl Storage for BinaryStorage {
+    fn read(&self, conf: &Config, data: &[u8]) -> Result<RasdfBase, RasdfError> {
+        let mut reader = Reader {
+            data,
+            pos: BINARY_MAGIC.len(),
+        };
+        let mut dbase = reader.header(conf)?;
+
+        let count = reader
+            .u32()
+            .ok_or_else(|| RasdfError::parse(&conf.datafile, String::from("no row count")))?;
+        for n in 1..=count {
+            let (path, data) = reader.row(dbase.version).ok_or_else(|| {
+                RasdfError::parse(&conf.datafile, format!("row {} of {} is truncated", n, count))
+            })?;
+            dbase.contents.insert(path, data);
+        }
+        Ok(dbase)
+    }
+
+    fn read_header(&self, conf: &Config, data: &[u8]) -> Result<RasdfBase, RasdfError> {
+        Reader {
+            data,
+            pos: BINARY_MAGIC.len(),
+        }
+        .header(conf)
+    }
+
+    fn write(&self, dbase: &RasdfBase, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(BINARY_MAGIC)?;
+        out.write_all(&DATAFILE_VERSION.to_le_bytes())?;
+        let fields = dbase.header_fields();
+        out.write_all(&(fields.len() as u32).to_le_bytes())?;
+        out.write_all(fields.as_bytes())?;
+        out.write_all(&(dbase.contents.len() as u32).to_le_bytes())?;
+        for (key, value) in &dbase.contents {
+            let path = path_bytes(key);
+            out.write_all(&(path.len() as u32).to_le_bytes())?;
+            out.write_all(&path)?;
+            out.write_all(&value.rating.to_le_bytes())?;
+            out.write_all(&value.date.to_le_bytes())?;
+            out.write_all(&(value.flags.len() as u32).to_le_bytes())?;
+            out.write_all(value.flags.as_bytes())?;
+            out.write_all(&(value.visits.len() as u32).to_le_bytes())?;
+            for date in &value.visits {
+                out.write_all(&date.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A cursor over the bytes of a binary datafile.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn header(&mut self, conf: &Config) -> Result<RasdfBase, RasdfError> {
+        let mut dbase = RasdfBase::new();
+        dbase.backend = Backend::Binary;
+
+        let version = self
+            .u32()
+            .ok_or_else(|| RasdfError::parse(&conf.datafile, String::from("no header")))?;
+        if version > DATAFILE_VERSION {
+            // can't know the layout of the rest
+            return Err(RasdfError::NewerVersion {
+                path: conf.datafile.clone(),
+                version,
+            });
+        }
+        if version < 4 {
+            dbase.version = version;
+            return Ok(dbase);
+        }
+
+        let fields = self
+            .u32()
+            .and_then(|len| self.bytes(len as usize))
+            .map(String::from_utf8_lossy)
+            .ok_or_else(|| RasdfError::parse(&conf.datafile, String::from("header is truncated")))?;
+        dbase.set_header(conf, &format!(" v{} {}", version, fields))?;
+        Ok(dbase)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes(8)?.try_into().ok()?))
+    }
+
+    fn row(&mut self, version: u32) -> Option<(std::path::PathBuf, RasdfBaseData)> {
+        let len = self.u32()? as usize;
+        let path = path_from_bytes(self.bytes(len)?.to_vec())?;
+        let rating = f32::from_le_bytes(self.bytes(4)?.try_into().ok()?);
+        if !valid_rating(rating) {
+            return None;
+        }
+        let date = self.u64()?;
+        let len = self.u32()? as usize;
+        let flags = String::from_utf8(self.bytes(len)?.to_vec()).ok()?;
+        let visits = match version {
+            5.. => {
+                let count = self.u32()?;
+                (0..count).map(|_| self.u64()).collect::<Option<_>>()?
+            }
+            _ => Vec::new(),
+        };
+        Some((
+            path,
+            RasdfBaseData {
+                rating,
+                date,
+                flags,
+                visits,
+            },
+        ))
+    }
+}
@@ -0,0 +1,206 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn make_temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rasdf-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::canonicalize(dir).unwrap()
+}
+
+/// Run rasdf with its datafile, settings and home all inside dir.
+fn rasdf(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rasdf"))
+        .args(args)
+        .env_clear()
+        .env("HOME", dir)
+        .env("RASDF_CONFIG", dir.join("config.toml"))
+        .env("RASDF_DATAFILE", dir.join("rasdf.dat"))
+        .env("RASDF_LOGFILE", dir.join("rasdf.log"))
+        .output()
+        .unwrap()
+}
+
+fn status(output: &Output) -> i32 {
+    output.status.code().unwrap()
+}
+
+#[test]
+fn test_find_exit_status() {
+    let dir = make_temp_dir("find");
+    let project = dir.join("project");
+    fs::create_dir(&project).unwrap();
+    assert_eq!(status(&rasdf(&dir, &["add", project.to_str().unwrap()])), 0);
+
+    let found = rasdf(&dir, &["find", "proj"]);
+    assert_eq!(status(&found), 0);
+    assert_eq!(found.stdout, format!("{}\n", project.display()).into_bytes());
+
+    let missing = rasdf(&dir, &["find", "nowhere"]);
+    assert_eq!(status(&missing), 1);
+    assert!(missing.stdout.is_empty());
+
+    assert_eq!(status(&rasdf(&dir, &["find-all", "proj"])), 0);
+    assert_eq!(status(&rasdf(&dir, &["find-all", "nowhere"])), 1);
+}
+
+#[test]
+fn test_add_and_remove_exit_status() {
+    let dir = make_temp_dir("add-remove");
+    let path = dir.to_str().unwrap();
+
+    // nothing that exists to add, or a blacklisted command
+    assert_eq!(status(&rasdf(&dir, &["add", "no-such-folder"])), 1);
+    assert_eq!(status(&rasdf(&dir, &["add", path, "ls"])), 1);
+    assert_eq!(status(&rasdf(&dir, &["add", path, "no-such-folder"])), 0);
+
+    assert_eq!(status(&rasdf(&dir, &["remove", path])), 0);
+    assert_eq!(status(&rasdf(&dir, &["remove", path])), 1);
+}
+
+#[test]
+fn test_history() {
+    let dir = make_temp_dir("history");
+    let path = dir.to_str().unwrap();
+    assert_eq!(status(&rasdf(&dir, &["history", path])), 1);
+
+    rasdf(&dir, &["add", path]);
+    rasdf(&dir, &["add", path]);
+    let history = rasdf(&dir, &["history", path]);
+    assert_eq!(status(&history), 0);
+    let history = String::from_utf8(history.stdout).unwrap();
+    let lines: Vec<&str> = history.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], path);
+}
+
+#[test]
+fn test_flag_command() {
+    let dir = make_temp_dir("flag");
+    let path = dir.to_str().unwrap();
+    assert_eq!(status(&rasdf(&dir, &["flag", "add", path, "p"])), 1);
+
+    rasdf(&dir, &["add", path]);
+    assert_eq!(status(&rasdf(&dir, &["flag", "add", path, "hw"])), 0);
+    assert_eq!(status(&rasdf(&dir, &["find", "flag"])), 1);
+    assert_eq!(status(&rasdf(&dir, &["flag", "remove", path, "h"])), 0);
+    assert_eq!(status(&rasdf(&dir, &["find", "--tags=w", "flag"])), 0);
+    assert_eq!(status(&rasdf(&dir, &["find", "--tags", "q", "flag"])), 1);
+
+    assert_eq!(status(&rasdf(&dir, &["flag", "add", path, "!"])), 2);
+    assert_eq!(status(&rasdf(&dir, &["flag", "toggle", path, "p"])), 2);
+    assert_eq!(status(&rasdf(&dir, &["flag", "add", path])), 2);
+}
+
+#[test]
+fn test_bookmark_commands() {
+    let dir = make_temp_dir("bookmark");
+    let path = dir.to_str().unwrap();
+    let project = dir.join("project");
+    fs::create_dir(&project).unwrap();
+    rasdf(&dir, &["add", project.to_str().unwrap()]);
+
+    assert_eq!(status(&rasdf(&dir, &["bookmark", "home", path])), 0);
+    assert_eq!(status(&rasdf(&dir, &["bookmark", "nope", "no-such-folder"])), 1);
+    assert_eq!(status(&rasdf(&dir, &["bookmark", "home"])), 2);
+
+    // the bookmark name wins, even though the project row matches it
+    fs::rename(&project, dir.join("home-project")).unwrap();
+    rasdf(&dir, &["add", dir.join("home-project").to_str().unwrap()]);
+    let found = rasdf(&dir, &["find", "home"]);
+    assert_eq!(found.stdout, format!("{}\n", path).into_bytes());
+
+    // but not past the filters: home is a folder, and not under project
+    let found = rasdf(&dir, &["find", "-f", "home"]);
+    assert_eq!(status(&found), 1);
+    let project = dir.join("home-project");
+    let found = rasdf(&dir, &["find", "home", "--under", project.to_str().unwrap()]);
+    assert_eq!(found.stdout, format!("{}/home-project\n", path).into_bytes());
+
+    let listed = rasdf(&dir, &["bookmarks"]);
+    assert_eq!(listed.stdout, format!("home {}\n", path).into_bytes());
+
+    assert_eq!(status(&rasdf(&dir, &["unbookmark", "home"])), 0);
+    assert_eq!(status(&rasdf(&dir, &["unbookmark", "home"])), 1);
+    let found = rasdf(&dir, &["find", "home"]);
+    assert_eq!(found.stdout, format!("{}/home-project\n", path).into_bytes());
+}
+
+#[test]
+fn test_ancestor_jump() {
+    let dir = make_temp_dir("ancestors");
+    let deep = dir.join("work/app/src");
+    fs::create_dir_all(&deep).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_rasdf"))
+        .args(["find", "..wo"])
+        .current_dir(&deep)
+        .env_clear()
+        .env("HOME", &dir)
+        .env("RASDF_DATAFILE", dir.join("rasdf.dat"))
+        .output()
+        .unwrap();
+    assert_eq!(status(&output), 0);
+    assert_eq!(output.stdout, format!("{}/work\n", dir.display()).into_bytes());
+
+    let deep = deep.to_str().unwrap();
+    assert_eq!(status(&rasdf(&dir, &["find", "..nowhere", "--from", deep])), 1);
+    assert_eq!(status(&rasdf(&dir, &["find-all", "-u", "a", "--from", deep])), 0);
+}
+
+#[test]
+fn test_project_flag() {
+    let dir = make_temp_dir("project");
+    for name in ["mine/docs", "theirs/docs"] {
+        fs::create_dir_all(dir.join(name)).unwrap();
+    }
+    fs::create_dir(dir.join("mine/.git")).unwrap();
+    let (mine, theirs) = (dir.join("mine/docs"), dir.join("theirs/docs"));
+    let (mine, theirs) = (mine.to_str().unwrap(), theirs.to_str().unwrap());
+    assert_eq!(status(&rasdf(&dir, &["add", mine, theirs, theirs])), 0);
+
+    let output = rasdf(&dir, &["find", "docs"]);
+    assert_eq!(output.stdout, format!("{}\n", theirs).into_bytes());
+    let output = Command::new(env!("CARGO_BIN_EXE_rasdf"))
+        .args(["find", "-p", "docs"])
+        .current_dir(dir.join("mine"))
+        .env_clear()
+        .env("HOME", &dir)
+        .env("RASDF_DATAFILE", dir.join("rasdf.dat"))
+        .output()
+        .unwrap();
+    assert_eq!(output.stdout, format!("{}\n", mine).into_bytes());
+    let output = rasdf(&dir, &["find", "--project", "--from", mine, "--no-project", "docs"]);
+    assert_eq!(output.stdout, format!("{}\n", theirs).into_bytes());
+}
+
+#[test]
+fn test_usage_exit_status() {
+    let dir = make_temp_dir("usage");
+    assert_eq!(status(&rasdf(&dir, &[])), 2);
+    assert_eq!(status(&rasdf(&dir, &["fnid", "foo"])), 2);
+    assert_eq!(status(&rasdf(&dir, &["find", "-x", "foo"])), 2);
+    assert_eq!(status(&rasdf(&dir, &["remove"])), 2);
+    assert_eq!(status(&rasdf(&dir, &["convert", "yaml"])), 2);
+    assert_eq!(status(&rasdf(&dir, &["find", "--regex", "(unclosed"])), 2);
+    assert_eq!(status(&rasdf(&dir, &["find", "--matching=perl", "foo"])), 2);
+    assert_eq!(status(&rasdf(&dir, &["help", "find"])), 0);
+}
+
+#[test]
+fn test_datafile_error_exit_status() {
+    let dir = make_temp_dir("datafile-error");
+    let original = "#rasdf v4 journal=1\nnot a row\n";
+    fs::write(dir.join("rasdf.dat"), original).unwrap();
+
+    assert_eq!(status(&rasdf(&dir, &["find", "foo"])), 3);
+    assert_eq!(status(&rasdf(&dir, &["clean"])), 3);
+    assert_eq!(fs::read_to_string(dir.join("rasdf.dat")).unwrap(), original);
+
+    // one that can't be read at all is as much a datafile error
+    let dir = make_temp_dir("datafile-unreadable");
+    fs::create_dir(dir.join("rasdf.dat")).unwrap();
+    assert_eq!(status(&rasdf(&dir, &["find", "foo"])), 3);
+    assert_eq!(status(&rasdf(&dir, &["remove", "foo"])), 3);
+}
@@ -1,28 +1,88 @@
-use std::path::PathBuf;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use proptest::prelude::*;
 
 use rasdf::*;
 
-fn make_config() -> config::Config<'static> {
-    config::Config{
+fn make_config() -> config::Config {
+    config::Config {
         version: "0.0.1.test".to_string(),
         executable: "test_harness".to_string(),
-        command: String::new(),
-        method: config::ScoreMethod::Frecency,
         datafile: PathBuf::from(".asdf.dat"),
-        tempfile: PathBuf::from("/tmp"),
         maxlines: 20usize,
         logging: Some(PathBuf::from("./test-log.log")),
         current_time: 123456,
-        find_dirs: true,
-        find_files: false,
-        strict: true,
-        case_sensitive: false,
-        flags: String::new(),
+        case: config::Case::Insensitive,
         cmd_blacklist: Vec::new(),
-        // entry_flags_add: Vec::new(),
-        // entry_flags_remove: Vec::new(),
-        arguments: Vec::new(),
+        removable: Vec::new(),
+        ..config::Config::defaults()
+    }
+}
+
+/// Make an empty scratch folder under the system temp dir.
+fn make_temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rasdf-test-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::canonicalize(&dir).unwrap()
+}
+
+/// Make a scratch folder holding each of names, and a database with a
+/// visit to each in turn, so that a name given twice is visited twice.
+fn make_tree_db(name: &str, names: &[&str]) -> (PathBuf, RasdfBase) {
+    let dir = make_temp_dir(name);
+    let conf = make_config();
+    let mut dbase = RasdfBase::new();
+    for name in names {
+        fs::create_dir_all(dir.join(name)).unwrap();
+        dbase.add_path(&conf, dir.join(name)).unwrap();
     }
+    (dir, dbase)
+}
+
+/// What find-all lists for terms, as paths relative to dir, in order.
+fn found(dbase: &RasdfBase, dir: &Path, conf: &mut config::Config, terms: &[&str]) -> Vec<PathBuf> {
+    conf.arguments = terms.iter().map(OsString::from).collect();
+    let mut paths: Vec<PathBuf> = dbase
+        .find_list(conf)
+        .iter()
+        .map(|(path, _)| path.strip_prefix(dir).unwrap().to_path_buf())
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// The folders above conf.from that terms find, as paths relative to
+/// dir, nearest last.
+fn found_ancestors(
+    dbase: &RasdfBase,
+    dir: &Path,
+    conf: &mut config::Config,
+    terms: &[&str],
+) -> Vec<PathBuf> {
+    conf.arguments = terms.iter().map(OsString::from).collect();
+    dbase
+        .find_ancestors(conf)
+        .into_iter()
+        .map(|(path, _)| path.strip_prefix(dir).unwrap().to_path_buf())
+        .collect()
+}
+
+/// names as paths, to compare with what found and found_ancestors list.
+fn paths(names: &[&str]) -> Vec<PathBuf> {
+    names.iter().map(PathBuf::from).collect()
+}
+
+/// Make a scratch home folder holding `tmp/tmp-one.file`.
+fn make_temp_home(name: &str) -> String {
+    let home = make_temp_dir(name);
+    fs::create_dir(home.join("tmp")).unwrap();
+    fs::write(home.join("tmp/tmp-one.file"), "").unwrap();
+    home.to_str().unwrap().to_string()
 }
 
 #[test]
@@ -36,9 +96,10 @@ fn test_fake_config() {
 #[test]
 fn test_make_database() {
     let conf = make_config();
+    let home = make_temp_home("make-database");
 
     let mut dbase = RasdfBase::new();
-    dbase.add_line(&conf, "/home/tim/tmp|2.2|123456|t");
+    dbase.add_line(&conf, &format!("{}/tmp|2.2|123456|t", home)).unwrap();
     assert!( dbase.len() == 1 );
 }
 
@@ -57,7 +118,7 @@ fn test_basedata_update() {
     let mut bdata = RasdfBaseData::new(
         &conf, Some(4.0), Some(121212), "tf"
     );
-    bdata.update_with(&RasdfBaseData::new(&conf, None, None, "tg"));
+    bdata.update_with(&conf, &RasdfBaseData::new(&conf, None, None, "tg"));
 
     assert_eq!(bdata.flags, "tfg");
     assert_eq!(bdata.date, conf.current_time);
@@ -67,19 +128,20 @@ fn test_basedata_update() {
 #[test]
 fn test_add_from_string() {
     let conf = make_config();
+    let home = make_temp_home("add-from-string");
 
     // Add two paths from a multi-line string
     let mut dbase = RasdfBase::from_data(&conf, 
-        "/home/tim/tmp|2.2|123456|td\n/home/tim/|1.9|123457|td"
-    );
+        &format!("{0}/tmp|2.2|123456|td\n{0}/|1.9|123457|td", home)
+    ).unwrap();
     assert!( dbase.len() == 2 );
     
     // Add one new path with add_line
-    dbase.add_line(&conf, "/home/tim/tmp/tmp-one.file|2.2|123456|t");
+    dbase.add_line(&conf, &format!("{}/tmp/tmp-one.file|2.2|123456|t", home)).unwrap();
     assert!( dbase.len() == 3 );
 
     // Add one repeated path, should be rejected.
-    dbase.add_line(&conf, "/home/tim/tmp|2.2|123456|t");
+    dbase.add_line(&conf, &format!("{}/tmp|2.2|123456|t", home)).unwrap();
     assert!( dbase.len() == 3 );
 }
 
@@ -88,7 +150,7 @@ fn test_add_path() {
     let conf = make_config();
 
     let mut dbase = RasdfBase::new();
-    dbase.add_path(&conf, "temp/my-temp-file");
+    dbase.add_path(&conf, "temp/my-temp-file").unwrap();
     assert_eq!( dbase.len(), 1 );
 }
 
@@ -102,10 +164,10 @@ fn test_repeated_segment() {
     conf.strict = true;
     conf.find_files = true;
     conf.find_dirs = false;
-    conf.arguments = ["temp".to_string()].to_vec();
+    conf.arguments = vec![OsString::from("temp")];
 
     let mut dbase = RasdfBase::new();
-    dbase.add_path(&conf, "temp/my-temp-file");
+    dbase.add_path(&conf, "temp/my-temp-file").unwrap();
     assert_eq!( dbase.len(), 1 );
 
     let found = dbase.find_list(&conf); 
@@ -113,3 +175,1004 @@ fn test_repeated_segment() {
 }
 
 
+
+#[test]
+fn test_write_out_round_trip() {
+    let dir = make_temp_dir("write-out");
+    let mut conf = make_config();
+    conf.datafile = dir.join("rasdf.dat");
+
+    let mut dbase = RasdfBase::new();
+    dbase.add_path(&conf, dir.to_str().unwrap()).unwrap();
+    dbase.write_out(&conf).unwrap();
+
+    let dbase = RasdfBase::from_file(&conf).unwrap();
+    assert_eq!(dbase.len(), 1);
+    assert!(dbase.entry(dir.to_str().unwrap()).is_some());
+
+    // only the datafile is left behind
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+}
+
+#[test]
+fn test_concurrent_write_out() {
+    let dir = make_temp_dir("concurrent");
+    let datafile = dir.join("rasdf.dat");
+
+    // every writer has its own set of rows
+    let mut writers = Vec::new();
+    for w in 0..8 {
+        let subdir = dir.join(format!("writer-{}", w));
+        fs::create_dir(&subdir).unwrap();
+        let datafile = datafile.clone();
+        writers.push(thread::spawn(move || {
+            let mut conf = make_config();
+            conf.datafile = datafile;
+            let mut dbase = RasdfBase::new();
+            for n in 0..=w {
+                let path = subdir.join(format!("{}", n));
+                fs::create_dir(&path).unwrap();
+                dbase.add_path(&conf, path.to_str().unwrap()).unwrap();
+            }
+            for _ in 0..20 {
+                dbase.write_out(&conf).unwrap();
+            }
+        }));
+    }
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    // the datafile must be exactly one writer's rows, never a mixture
+    let mut conf = make_config();
+    conf.datafile = datafile;
+    let dbase = RasdfBase::from_file(&conf).unwrap();
+    let writer = (0..8)
+        .find(|w| dbase.entry(dir.join(format!("writer-{}/0", w)).to_str().unwrap()).is_some())
+        .expect("no complete set of rows in datafile");
+    assert_eq!(dbase.len(), writer + 1);
+
+    // and no temp files are left lying around
+    let leftovers = fs::read_dir(&dir)
+        .unwrap()
+        .filter(|e| e.as_ref().unwrap().file_name().to_string_lossy().ends_with(".tmp"))
+        .count();
+    assert_eq!(leftovers, 0);
+}
+
+#[test]
+fn test_lock_timeout() {
+    let dir = make_temp_dir("lock-timeout");
+    let mut conf = make_config();
+    conf.datafile = dir.join("rasdf.dat");
+    conf.lock_timeout = 50;
+
+    let held = lock::DataLock::acquire(&conf).unwrap();
+    let err = lock::DataLock::acquire(&conf).err().expect("lock taken twice");
+    assert!(matches!(err, error::RasdfError::LockTimeout { timeout: 50, .. }));
+    assert_eq!(err.exit_code(), 3);
+
+    // free again once the first holder lets go
+    drop(held);
+    assert!(lock::DataLock::acquire(&conf).is_ok());
+}
+
+#[test]
+fn test_locked_updates_are_not_lost() {
+    let dir = make_temp_dir("locked-updates");
+    let datafile = dir.join("rasdf.dat");
+
+    // each writer adds its own path in a locked read-modify-write
+    let mut writers = Vec::new();
+    for w in 0..8 {
+        let path = dir.join(format!("writer-{}", w));
+        fs::create_dir(&path).unwrap();
+        let datafile = datafile.clone();
+        writers.push(thread::spawn(move || {
+            let mut conf = make_config();
+            conf.datafile = datafile;
+            let _lock = lock::DataLock::acquire(&conf).unwrap();
+            let mut dbase = RasdfBase::from_file(&conf).unwrap();
+            dbase.add_path(&conf, path.to_str().unwrap()).unwrap();
+            dbase.write_out(&conf).unwrap();
+        }));
+    }
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    let mut conf = make_config();
+    conf.datafile = datafile;
+    assert_eq!(RasdfBase::from_file(&conf).unwrap().len(), 8);
+}
+
+#[test]
+fn test_datafile_header() {
+    let conf = make_config();
+    let home = make_temp_home("datafile-header");
+
+    let dbase = RasdfBase::from_data(&conf, &format!("#rasdf v2\n{}/tmp|2.2|123456|t\n", home)).unwrap();
+    assert_eq!(dbase.version(), 2);
+    assert_eq!(dbase.len(), 1);
+
+    // a file with no header is the original format
+    let dbase = RasdfBase::from_data(&conf, &format!("{}/tmp|2.2|123456|t\n", home)).unwrap();
+    assert_eq!(dbase.version(), 1);
+    assert_eq!(dbase.len(), 1);
+}
+
+#[test]
+fn test_migrate_v1_datafile() {
+    let dir = make_temp_dir("migrate-v1");
+    let mut conf = make_config();
+    conf.datafile = dir.join("rasdf.dat");
+    fs::write(&conf.datafile, format!("{}|2.2|123456|t\n", dir.display())).unwrap();
+
+    let dbase = RasdfBase::from_file(&conf).unwrap();
+    assert_eq!(dbase.version(), 1);
+    dbase.write_out(&conf).unwrap();
+
+    let contents = fs::read_to_string(&conf.datafile).unwrap();
+    assert!(contents.starts_with(&format!("#rasdf v{} ", DATAFILE_VERSION)));
+    let dbase = RasdfBase::from_file(&conf).unwrap();
+    assert_eq!(dbase.version(), DATAFILE_VERSION);
+    assert_eq!(dbase.len(), 1);
+}
+
+#[test]
+fn test_newer_datafile_not_overwritten() {
+    let dir = make_temp_dir("newer-version");
+    let mut conf = make_config();
+    conf.datafile = dir.join("rasdf.dat");
+    let original = format!("#rasdf v{}\n{}|2.2|123456|t\n", DATAFILE_VERSION + 1, dir.display());
+    fs::write(&conf.datafile, &original).unwrap();
+
+    let err = RasdfBase::from_file(&conf).err().expect("newer datafile read");
+    assert!(matches!(err, error::RasdfError::NewerVersion { version, .. } if version == DATAFILE_VERSION + 1));
+    assert!(journal::append(&conf, &[dir.clone().into_os_string()]).is_err());
+    assert_eq!(fs::read_to_string(&conf.datafile).unwrap(), original);
+}
+
+#[test]
+fn test_v2_paths_are_not_unescaped() {
+    let dir = make_temp_dir("v2-paths");
+    let path = dir.join("100%25");
+    fs::create_dir(&path).unwrap();
+
+    // before v3 a '%' in a path was written as is
+    let conf = make_config();
+    let dbase = RasdfBase::from_data(&conf, &format!("#rasdf v2\n{}|1|123456|\n", path.display())).unwrap();
+    assert!(dbase.entry(path.to_str().unwrap()).is_some());
+}
+
+proptest! {
+    #[test]
+    fn prop_encode_path_round_trip(bytes in proptest::collection::vec(any::<u8>(), 0..64)) {
+        let path = Path::new(OsStr::from_bytes(&bytes));
+        let encoded = encoding::encode_path(path);
+        prop_assert!(!encoded.contains(['|', '\n']));
+        prop_assert_eq!(encoding::decode_path(&encoded), Some(path.to_path_buf()));
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn prop_path_survives_write_out(name in "[a-z%|\\\n\r ~é]{1,12}") {
+        prop_assume!(name != "." && name != "..");
+        let dir = make_temp_dir("prop-paths");
+        let path = dir.join(&name);
+        fs::create_dir(&path).unwrap();
+        let path = path.to_str().unwrap();
+
+        let mut conf = make_config();
+        conf.datafile = dir.join("rasdf.dat");
+        let mut dbase = RasdfBase::new();
+        dbase.add_path(&conf, path).unwrap();
+        dbase.write_out(&conf).unwrap();
+
+        let dbase = RasdfBase::from_file(&conf).unwrap();
+        prop_assert_eq!(dbase.len(), 1);
+        prop_assert!(dbase.entry(path).is_some());
+    }
+}
+
+#[test]
+fn test_non_utf8_path() {
+    let dir = make_temp_dir("non-utf8");
+    let path = dir.join(OsStr::from_bytes(b"Caf\xe9"));
+    fs::create_dir(&path).unwrap();
+
+    let mut conf = make_config();
+    conf.datafile = dir.join("rasdf.dat");
+    let mut dbase = RasdfBase::new();
+    dbase.add_path(&conf, &path).unwrap();
+    dbase.write_out(&conf).unwrap();
+
+    let dbase = RasdfBase::from_file(&conf).unwrap();
+    assert!(dbase.entry(&path).is_some());
+
+    // matched byte for byte, and case folded where it can be
+    conf.arguments = vec![OsString::from("caf")];
+    assert_eq!(dbase.find(&conf), Some(path.as_path()));
+    conf.arguments = vec![OsStr::from_bytes(b"f\xe9").to_os_string()];
+    assert_eq!(dbase.find(&conf), Some(path.as_path()));
+    conf.arguments = vec![OsString::from("cafe")];
+    assert_eq!(dbase.find(&conf), None);
+}
+
+#[test]
+fn test_binary_round_trip() {
+    let dir = make_temp_dir("binary");
+    let mut conf = make_config();
+    conf.datafile = dir.join("rasdf.dat");
+    conf.backend = config::Backend::Binary;
+
+    let path = dir.join("a|b\nc");
+    fs::create_dir(&path).unwrap();
+    let mut dbase = RasdfBase::from_file(&conf).unwrap();
+    dbase.add_path(&conf, &dir).unwrap();
+    dbase.add_path(&conf, &path).unwrap();
+    dbase.add_path(&conf, &path).unwrap();
+    dbase.write_out(&conf).unwrap();
+    assert!(fs::read(&conf.datafile).unwrap().starts_with(b"RASDFBIN"));
+
+    // the format is recognised whatever conf.backend says
+    conf.backend = config::Backend::Text;
+    let dbase = RasdfBase::from_file(&conf).unwrap();
+    assert_eq!(dbase.backend(), config::Backend::Binary);
+    assert_eq!(dbase.len(), 2);
+    assert_eq!(dbase.entry(&path).unwrap().rating, 2.0);
+    assert_eq!(dbase.entry(&path).unwrap().date, conf.current_time);
+}
+
+#[test]
+fn test_convert_backends() {
+    let dir = make_temp_dir("convert");
+    let mut conf = make_config();
+    conf.datafile = dir.join("rasdf.dat");
+    fs::write(&conf.datafile, format!("#rasdf v3\n{}|2.5|123|tf\n", dir.display())).unwrap();
+
+    let mut dbase = RasdfBase::from_file(&conf).unwrap();
+    dbase.set_backend(config::Backend::Binary);
+    dbase.write_out(&conf).unwrap();
+
+    let mut dbase = RasdfBase::from_file(&conf).unwrap();
+    assert_eq!(dbase.backend(), config::Backend::Binary);
+    dbase.set_backend(config::Backend::Text);
+    dbase.write_out(&conf).unwrap();
+
+    assert_eq!(
+        fs::read_to_string(&conf.datafile).unwrap(),
+        format!("#rasdf v{} journal=2\n{}|2.5|123|tf\n", DATAFILE_VERSION, dir.display())
+    );
+}
+
+#[test]
+fn test_newer_binary_not_overwritten() {
+    let dir = make_temp_dir("newer-binary");
+    let mut conf = make_config();
+    conf.datafile = dir.join("rasdf.dat");
+    let mut original = b"RASDFBIN".to_vec();
+    original.extend_from_slice(&(DATAFILE_VERSION + 1).to_le_bytes());
+    original.extend_from_slice(&[3, 0, 0, 0, 9, 9, 9]);
+    fs::write(&conf.datafile, &original).unwrap();
+
+    let err = RasdfBase::from_file(&conf).err().expect("newer datafile read");
+    assert_eq!(err.exit_code(), 3);
+    assert!(journal::append(&conf, &[dir.clone().into_os_string()]).is_err());
+    assert_eq!(fs::read(&conf.datafile).unwrap(), original);
+}
+
+#[test]
+fn test_journal_add() {
+    let dir = make_temp_dir("journal-add");
+    let mut conf = make_config();
+    conf.datafile = dir.join("rasdf.dat");
+    fs::write(&conf.datafile, format!("#rasdf v4 journal=5\n{}|1|100|\n", dir.display())).unwrap();
+    let original = fs::read(&conf.datafile).unwrap();
+
+    let other = dir.join("other");
+    fs::create_dir(&other).unwrap();
+    let paths = vec![dir.clone().into_os_string(), other.clone().into_os_string()];
+    journal::append(&conf, &paths).unwrap();
+    journal::append(&conf, &paths[1..]).unwrap();
+
+    // the datafile is untouched, but reading it replays the journal
+    assert_eq!(fs::read(&conf.datafile).unwrap(), original);
+    let dbase = RasdfBase::from_file(&conf).unwrap();
+    assert_eq!(dbase.len(), 2);
+    assert_eq!(dbase.entry(&dir).unwrap().rating, 2.0);
+    assert_eq!(dbase.entry(&dir).unwrap().date, conf.current_time);
+    assert_eq!(dbase.entry(&other).unwrap().rating, 2.0);
+
+    // writing the datafile merges the journal
+    dbase.write_out(&conf).unwrap();
+    assert!(!journal::journal_path(&conf.datafile).exists());
+    let dbase = RasdfBase::from_file(&conf).unwrap();
+    assert_eq!(dbase.journal(), 6);
+    assert_eq!(dbase.entry(&other).unwrap().rating, 2.0);
+}
+
+#[test]
+fn test_journal_not_replayed_twice() {
+    let dir = make_temp_dir("journal-twice");
+    let mut conf = make_config();
+    conf.datafile = dir.join("rasdf.dat");
+    journal::append(&conf, &[dir.clone().into_os_string()]).unwrap();
+    let journal = fs::read(journal::journal_path(&conf.datafile)).unwrap();
+
+    // as if rasdf stopped between writing the datafile and removing the journal
+    RasdfBase::from_file(&conf).unwrap().write_out(&conf).unwrap();
+    fs::write(journal::journal_path(&conf.datafile), &journal).unwrap();
+    assert_eq!(RasdfBase::from_file(&conf).unwrap().entry(&dir).unwrap().rating, 1.0);
+
+    // and the stale journal is replaced by the next add
+    journal::append(&conf, &[dir.clone().into_os_string()]).unwrap();
+    assert_eq!(RasdfBase::from_file(&conf).unwrap().entry(&dir).unwrap().rating, 2.0);
+}
+
+#[test]
+fn test_journal_partial_row() {
+    let dir = make_temp_dir("journal-partial");
+    let mut conf = make_config();
+    conf.datafile = dir.join("rasdf.dat");
+    let row = format!("{}|{}\n", dir.display(), conf.current_time);
+    fs::write(
+        journal::journal_path(&conf.datafile),
+        format!("#journal 0\n{}{}", row, &row[..row.len() - 4]),
+    )
+    .unwrap();
+
+    // the cut-off final row is ignored
+    assert_eq!(RasdfBase::from_file(&conf).unwrap().entry(&dir).unwrap().rating, 1.0);
+
+    // and dropped before the next visit is appended, not run into it
+    assert_eq!(journal::append(&conf, &[dir.clone().into_os_string()]).unwrap().0, 1);
+    let journalled = fs::read_to_string(journal::journal_path(&conf.datafile)).unwrap();
+    assert_eq!(journalled, format!("#journal 0\n{}{}", row, row));
+    assert!(RasdfBase::from_file(&conf).unwrap().entry(&dir).unwrap().rating > 1.0);
+}
+
+#[test]
+fn test_corrupt_datafile_not_overwritten() {
+    let dir = make_temp_dir("corrupt");
+    let mut conf = make_config();
+    conf.datafile = dir.join("rasdf.dat");
+    let original = format!("#rasdf v4 journal=1\n{}|1|100|\nnot a row\n", dir.display());
+    fs::write(&conf.datafile, &original).unwrap();
+
+    // the bad row is reported by line, and nothing is written
+    let err = RasdfBase::from_file(&conf).err().expect("corrupt datafile read");
+    assert_eq!(err.to_string(), format!("{}: line 3: can't parse row: not a row", conf.datafile.display()));
+    assert_eq!(err.exit_code(), 3);
+    assert!(journal::append(&conf, &[dir.clone().into_os_string()]).is_ok());
+    assert!(RasdfBase::from_file(&conf).is_err());
+    assert_eq!(fs::read_to_string(&conf.datafile).unwrap(), original);
+
+    // as is a rating that rasdf could never have written
+    for rating in ["NaN", "inf", "-5"] {
+        let row = format!("{}|{}|100|", dir.display(), rating);
+        fs::write(&conf.datafile, format!("#rasdf v4 journal=1\n{}\n", row)).unwrap();
+        let err = RasdfBase::from_file(&conf).err().expect("bad rating read");
+        let message = format!("{}: line 2: can't parse row: {}", conf.datafile.display(), row);
+        assert_eq!(err.to_string(), message);
+    }
+
+    // a binary file cut short is as bad
+    fs::write(&conf.datafile, b"RASDFBIN\x04\x00\x00\x00\x00\x00\x00\x00\x02\x00\x00\x00").unwrap();
+    assert!(matches!(RasdfBase::from_file(&conf), Err(error::RasdfError::Parse { .. })));
+}
+
+#[test]
+fn test_unreadable_datafile() {
+    let dir = make_temp_dir("unreadable");
+    let mut conf = make_config();
+    conf.datafile = dir.clone();
+
+    let err = RasdfBase::from_file(&conf).err().expect("folder read as datafile");
+    assert!(matches!(err, error::RasdfError::Io { .. }));
+    assert_eq!(err.exit_code(), 3);
+
+    // a missing datafile is just a new database
+    conf.datafile = dir.join("missing.dat");
+    assert!(RasdfBase::from_file(&conf).unwrap().is_empty());
+}
+
+#[test]
+fn test_corrupt_journal_not_merged() {
+    let dir = make_temp_dir("corrupt-journal");
+    let mut conf = make_config();
+    conf.datafile = dir.join("rasdf.dat");
+    let journal = format!("#journal 0\n{}|{}\nnot a row\n", dir.display(), conf.current_time);
+    fs::write(journal::journal_path(&conf.datafile), &journal).unwrap();
+
+    let err = RasdfBase::from_file(&conf).err().expect("corrupt journal read");
+    assert!(matches!(err, error::RasdfError::Parse { line: Some(3), .. }));
+    assert_eq!(fs::read_to_string(journal::journal_path(&conf.datafile)).unwrap(), journal);
+}
+
+#[test]
+fn test_visit_history() {
+    let dir = make_temp_dir("history");
+    let mut conf = make_config();
+    conf.datafile = dir.join("rasdf.dat");
+    conf.history = 3;
+
+    // four visits a day apart, through the journal
+    for day in 0..4 {
+        conf.current_time = 10_000_000 + day * 86400;
+        journal::append(&conf, &[dir.clone().into_os_string()]).unwrap();
+    }
+    let dbase = RasdfBase::from_file(&conf).unwrap();
+    let visits = [10_086_400, 10_172_800, 10_259_200];
+    assert_eq!(dbase.entry(&dir).unwrap().visits, visits);
+
+    // kept in both formats
+    dbase.write_out(&conf).unwrap();
+    let contents = fs::read_to_string(&conf.datafile).unwrap();
+    assert!(contents.ends_with("|10086400,10172800,10259200\n"));
+    let mut dbase = RasdfBase::from_file(&conf).unwrap();
+    assert_eq!(dbase.entry(&dir).unwrap().visits, visits);
+    dbase.set_backend(config::Backend::Binary);
+    dbase.write_out(&conf).unwrap();
+    let dbase = RasdfBase::from_file(&conf).unwrap();
+    assert_eq!(dbase.entry(&dir).unwrap().visits, visits);
+
+    // scored on the visits themselves
+    let data = dbase.entry(&dir).unwrap();
+    conf.method = config::ScoreMethod::Visits;
+    assert_eq!(data.score(&conf), 6.0 + 4.0 + 2.0);
+    conf.method = config::ScoreMethod::Recent;
+    conf.recent_days = 1;
+    assert_eq!(data.score(&conf), 2.0);
+
+    // a v4 row can't have visits
+    let row = format!("#rasdf v4\n{}|1|100||100\n", dir.display());
+    assert!(RasdfBase::from_data(&conf, &row).is_err());
+}
+
+#[test]
+fn test_clean_ages_by_time() {
+    let dir = make_temp_dir("clean-ages");
+    let mut conf = make_config();
+    conf.current_time = 10_000_000;
+    for name in ["busy", "quiet", "rare"] {
+        fs::create_dir(dir.join(name)).unwrap();
+    }
+    let rows = format!(
+        "{0}/busy|8|100|\n{0}/quiet|0.3|100|\n{0}/rare|0.15|100|\n",
+        dir.display()
+    );
+
+    // the first clean only notes the time
+    let mut dbase = RasdfBase::from_data(&conf, &format!("#rasdf v5\n{}", rows)).unwrap();
+    assert!(dbase.clean(&conf));
+    assert_eq!(dbase.cleaned(), conf.current_time);
+    assert_eq!(dbase.entry(dir.join("busy")).unwrap().rating, 8.0);
+
+    // after one half-life, everything is halved and the rare row goes
+    let header = format!("#rasdf v5 journal=1 cleaned={}\n", conf.current_time - conf.age_half_life);
+    let mut dbase = RasdfBase::from_data(&conf, &format!("{}{}", header, rows)).unwrap();
+    assert!(dbase.clean(&conf));
+    assert_eq!(dbase.len(), 2);
+    assert_eq!(dbase.entry(dir.join("busy")).unwrap().rating, 4.0);
+    assert_eq!(dbase.entry(dir.join("quiet")).unwrap().rating, 0.15);
+
+    // cleaning again straight away changes nothing
+    assert!(!dbase.clean(&conf));
+
+    // the time of the clean is kept in the datafile
+    conf.datafile = dir.join("rasdf.dat");
+    dbase.write_out(&conf).unwrap();
+    assert_eq!(RasdfBase::from_file(&conf).unwrap().cleaned(), conf.current_time);
+
+    // and the size limit still applies on its own
+    conf.maxlines = 1;
+    assert!(dbase.clean(&conf));
+    assert!(dbase.entry(dir.join("busy")).is_some());
+    assert_eq!(dbase.len(), 1);
+}
+
+#[test]
+fn test_missing_paths() {
+    let dir = make_temp_dir("missing");
+    let mut conf = make_config();
+    conf.current_time = 10_000_000;
+    conf.arguments = vec![OsString::from("gone")];
+    let week = conf.missing_grace;
+    let rows = format!(
+        "#rasdf v5\n{0}/gone-old|1|{1}|\n{0}/gone-new|1|{2}|\n{0}/gone-usb|1|{1}|r\n",
+        dir.display(),
+        conf.current_time - week,
+        conf.current_time - week + 1,
+    );
+
+    // kept on reading, but never found
+    let mut dbase = RasdfBase::from_data(&conf, &rows).unwrap();
+    assert_eq!(dbase.len(), 3);
+    assert!(dbase.find_list(&conf).is_empty());
+    fs::create_dir(dir.join("gone-usb")).unwrap();
+    assert_eq!(dbase.find_list(&conf).len(), 1);
+    fs::remove_dir(dir.join("gone-usb")).unwrap();
+
+    // pruned by clean once the grace period is over, unless removable
+    assert!(dbase.clean(&conf));
+    assert!(dbase.entry(dir.join("gone-old")).is_none());
+    assert!(dbase.entry(dir.join("gone-new")).is_some());
+    assert!(dbase.entry(dir.join("gone-usb")).is_some());
+
+    // paths on removable mounts are flagged as they are added
+    conf.removable = vec![format!("{}/usb*", dir.display())];
+    fs::create_dir(dir.join("usb1")).unwrap();
+    dbase.add_path(&conf, dir.join("usb1")).unwrap();
+    assert_eq!(dbase.entry(dir.join("usb1")).unwrap().flags, "r");
+}
+
+#[test]
+fn test_row_flags() {
+    let dir = make_temp_dir("flags");
+    let mut conf = make_config();
+    conf.current_time = 10_000_000;
+    conf.strict = false;
+    conf.arguments = vec![OsString::from("proj")];
+    for name in ["proj-busy", "proj-pinned", "proj-hidden", "proj-work"] {
+        fs::create_dir(dir.join(name)).unwrap();
+    }
+    let rows = format!(
+        "#rasdf v5\n{0}/proj-busy|9|100|\n{0}/proj-pinned|0.01|100|p\n{0}/proj-hidden|20|100|h\n\
+         {0}/proj-work|1|100|w\n{0}/gone|0.01|100|p\n",
+        dir.display()
+    );
+    let mut dbase = RasdfBase::from_data(&conf, &rows).unwrap();
+
+    // pinned beats any score, hidden is never found, tags filter
+    assert_eq!(dbase.find(&conf), Some(dir.join("proj-pinned").as_path()));
+    assert_eq!(dbase.find_list(&conf).len(), 3);
+    conf.tags = String::from("w");
+    assert_eq!(dbase.find(&conf), Some(dir.join("proj-work").as_path()));
+    conf.tags.clear();
+
+    // pinned rows are not aged, faded, pruned or truncated by clean
+    conf.maxlines = 1;
+    dbase.clean(&conf);
+    assert!(dbase.entry(dir.join("gone")).is_some());
+    assert_eq!(dbase.entry(dir.join("proj-pinned")).unwrap().rating, 0.01);
+    assert_eq!(dbase.len(), 2);
+
+    // flags set and cleared by path
+    assert!(dbase.set_flags(dir.join("proj-pinned"), "hx", true));
+    assert_eq!(dbase.entry(dir.join("proj-pinned")).unwrap().flags, "phx");
+    assert!(dbase.set_flags(dir.join("proj-pinned"), "ph", false));
+    assert_eq!(dbase.entry(dir.join("proj-pinned")).unwrap().flags, "x");
+    assert!(!dbase.set_flags(dir.join("proj-busy"), "p", true));
+}
+
+#[test]
+fn test_bookmarks() {
+    let dir = make_temp_dir("bookmarks");
+    let mut conf = make_config();
+    conf.datafile = dir.join("rasdf.dat");
+    let target = dir.join("odd|name");
+    fs::create_dir(&target).unwrap();
+
+    let mut bookmarks = bookmarks::Bookmarks::from_file(&conf).unwrap();
+    assert!(bookmarks.set("dots", &target).unwrap().is_none());
+    assert!(bookmarks.set("gone", dir.join("missing")).is_err());
+    bookmarks.write_out(&conf).unwrap();
+
+    // read back, and found only by a single exact term
+    let mut bookmarks = bookmarks::Bookmarks::from_file(&conf).unwrap();
+    assert_eq!(bookmarks.get("dots"), Some(target.as_path()));
+    conf.arguments = vec![OsString::from("dots")];
+    assert_eq!(bookmarks.find(&conf), Some(target.as_path()));
+    conf.arguments = vec![OsString::from("dot")];
+    assert_eq!(bookmarks.find(&conf), None);
+    conf.arguments = vec![OsString::from("dots"), OsString::from("name")];
+    assert_eq!(bookmarks.find(&conf), None);
+
+    // and it has to get past the filters of the find, as a row would
+    let mut dbase = RasdfBase::new();
+    assert!(dbase.allows(&conf, &target));
+    conf.find_dirs = false;
+    assert!(!dbase.allows(&conf, &target));
+    conf.find_dirs = true;
+    conf.under = Some(dir.join("elsewhere"));
+    assert!(!dbase.allows(&conf, &target));
+    conf.under = None;
+    dbase.add_path(&conf, &target).unwrap();
+    dbase.set_flags(&target, "h", true);
+    assert!(!dbase.allows(&conf, &target));
+
+    // a bookmark whose path has gone finds nothing
+    conf.arguments = vec![OsString::from("dots")];
+    fs::remove_dir(&target).unwrap();
+    assert_eq!(bookmarks.find(&conf), None);
+
+    assert!(bookmarks.remove("dots"));
+    assert!(!bookmarks.remove("dots"));
+    assert_eq!(bookmarks.iter().count(), 0);
+
+    fs::write(bookmarks::bookmarks_path(&conf.datafile), "ok|/tmp\nno bar\n").unwrap();
+    assert!(bookmarks::Bookmarks::from_file(&conf).is_err());
+}
+
+#[test]
+fn test_fuzzy_matching() {
+    let (dir, dbase) = make_tree_db("fuzzy", &["rust/rasdf", "rust/sdf/lib", "resolved-f"]);
+    let mut conf = make_config();
+    conf.arguments = vec![OsString::from("rsdf")];
+
+    // literal matching finds none of them
+    assert!(dbase.find_list(&conf).is_empty());
+
+    // fuzzy finds all of them but the one where the match leaves the
+    // last segment, and ranks the closer match first among equals
+    conf.matching = config::Matching::Fuzzy;
+    let found = dbase.find_list(&conf);
+    assert_eq!(found.len(), 2);
+    assert_eq!(dbase.find(&conf), Some(dir.join("rust/rasdf").as_path()));
+    conf.strict = false;
+    assert_eq!(dbase.find_list(&conf).len(), 3);
+
+    // a much better score still wins over a closer match
+    let rows = format!(
+        "#rasdf v5\n{0}/rust/rasdf|1|{1}|\n{0}/resolved-f|50|{1}|\n",
+        dir.display(),
+        conf.current_time
+    );
+    let dbase = RasdfBase::from_data(&conf, &rows).unwrap();
+    assert_eq!(dbase.find(&conf), Some(dir.join("resolved-f").as_path()));
+}
+
+#[test]
+fn test_smart_case() {
+    let (dir, dbase) =
+        make_tree_db("smart-case", &["Work/Docs", "Work/docs", "work/Docs", "work/docs"]);
+    let mut conf = make_config();
+    conf.case = config::Case::Smart;
+    conf.strict = false;
+
+    // all lower case ignores case; any upper-case letter makes that
+    // term, and only that term, match case
+    for matching in [config::Matching::Literal, config::Matching::Fuzzy] {
+        conf.matching = matching;
+        assert_eq!(found(&dbase, &dir, &mut conf, &["docs"]).len(), 4);
+        let docs = paths(&["Work/Docs", "work/Docs"]);
+        assert_eq!(found(&dbase, &dir, &mut conf, &["Docs"]), docs);
+        assert_eq!(found(&dbase, &dir, &mut conf, &["work", "Docs"]), docs);
+        let work = paths(&["Work/Docs", "Work/docs"]);
+        assert_eq!(found(&dbase, &dir, &mut conf, &["Work", "docs"]), work);
+        assert_eq!(found(&dbase, &dir, &mut conf, &["Work", "Docs"]), paths(&["Work/Docs"]));
+    }
+
+    // the other modes treat every term alike
+    conf.matching = config::Matching::Literal;
+    conf.case = config::Case::Insensitive;
+    assert_eq!(found(&dbase, &dir, &mut conf, &["Work", "Docs"]).len(), 4);
+    conf.case = config::Case::Sensitive;
+    assert_eq!(found(&dbase, &dir, &mut conf, &["work", "docs"]), paths(&["work/docs"]));
+}
+
+#[test]
+fn test_query_syntax() {
+    let (dir, dbase) = make_tree_db("query", &["src/rasdf", "target/src", "srcs/old"]);
+    let mut conf = make_config();
+    conf.strict = false;
+
+    conf.matching = config::Matching::Literal;
+    assert_eq!(found(&dbase, &dir, &mut conf, &["src"]).len(), 3);
+    let both = paths(&["src/rasdf", "target/src"]);
+    assert_eq!(found(&dbase, &dir, &mut conf, &["^src$"]), both);
+    assert_eq!(found(&dbase, &dir, &mut conf, &["src$", "!target"]), paths(&["src/rasdf"]));
+    let both = paths(&["src/rasdf", "srcs/old"]);
+    assert_eq!(found(&dbase, &dir, &mut conf, &["src", "/"]), both);
+
+    conf.matching = config::Matching::Fuzzy;
+    assert_eq!(found(&dbase, &dir, &mut conf, &["^trg", "src$"]), paths(&["target/src"]));
+    let both = paths(&["src/rasdf", "target/src"]);
+    assert_eq!(found(&dbase, &dir, &mut conf, &["sr", "!old"]), both);
+
+    conf.matching = config::Matching::Regex;
+    let both = paths(&["src/rasdf", "srcs/old"]);
+    assert_eq!(found(&dbase, &dir, &mut conf, &["/srcs?/"]), both);
+    assert_eq!(found(&dbase, &dir, &mut conf, &["src$"]), paths(&["target/src"]));
+    // a pattern that can't be read finds nothing
+    assert!(found(&dbase, &dir, &mut conf, &["(src"]).is_empty());
+
+    conf.matching = config::Matching::Glob;
+    let both = paths(&["src/rasdf", "srcs/old"]);
+    assert_eq!(found(&dbase, &dir, &mut conf, &["src*", "*d*"]), both);
+    let both = paths(&["src/rasdf", "target/src"]);
+    assert_eq!(found(&dbase, &dir, &mut conf, &["src"]), both);
+}
+
+#[test]
+fn test_segment_matching() {
+    let (dir, dbase) = make_tree_db(
+        "segments",
+        &["mono/my-files.txt", "my/files/txt", "amy/files/txt"],
+    );
+    let mut conf = make_config();
+    conf.strict = false;
+
+    for matching in [config::Matching::Literal, config::Matching::Fuzzy] {
+        conf.matching = matching;
+        conf.segments = false;
+        conf.anchor_first = false;
+        assert_eq!(found(&dbase, &dir, &mut conf, &["my", "ile", "tx"]).len(), 3);
+
+        // one term to a segment, in order
+        conf.segments = true;
+        let both = paths(&["amy/files/txt", "my/files/txt"]);
+        assert_eq!(found(&dbase, &dir, &mut conf, &["my", "ile", "tx"]), both);
+        assert_eq!(found(&dbase, &dir, &mut conf, &["mon", "my"]), paths(&["mono/my-files.txt"]));
+
+        // and the first at the start of one
+        conf.anchor_first = true;
+        assert_eq!(found(&dbase, &dir, &mut conf, &["my", "ile", "tx"]), paths(&["my/files/txt"]));
+    }
+}
+
+#[test]
+fn test_find_modes() {
+    let (dir, dbase) = make_tree_db(
+        "find-modes",
+        &["proj/src/proj-docs/deep", "other/src", "other/proj", "proj/lib"],
+    );
+    let mut conf = make_config();
+
+    // ancestors of a folder, nearest last, found or not in the database,
+    // by their own names only, strict or not
+    conf.strict = false;
+    conf.from = Some(dir.join("proj/src/proj-docs/deep"));
+    let projects = paths(&["proj", "proj/src/proj-docs"]);
+    assert_eq!(found_ancestors(&dbase, &dir, &mut conf, &["..proj"]), projects);
+    assert!(query::ancestor_search(&conf));
+    conf.strict = true;
+    assert_eq!(found_ancestors(&dbase, &dir, &mut conf, &["..proj"]), projects);
+    assert_eq!(found_ancestors(&dbase, &dir, &mut conf, &["..src"]), paths(&["proj/src"]));
+    conf.arguments = vec![OsString::from("proj")];
+    assert!(!query::ancestor_search(&conf));
+    conf.ancestors = true;
+    conf.basename = true;
+    assert_eq!(found_ancestors(&dbase, &dir, &mut conf, &["proj"]), paths(&["proj"]));
+    conf.ancestors = false;
+    conf.strict = false;
+
+    // an exact basename, from the database
+    assert_eq!(dbase.find(&conf), Some(dir.join("other/proj").as_path()));
+    conf.arguments = vec![OsString::from("src")];
+    assert_eq!(dbase.find_list(&conf).len(), 1);
+    conf.basename = false;
+    assert_eq!(dbase.find_list(&conf).len(), 2);
+
+    // only in one subtree
+    conf.under = Some(dir.join("other"));
+    assert_eq!(dbase.find(&conf), Some(dir.join("other/src").as_path()));
+    conf.arguments = vec![OsString::from("lib")];
+    assert_eq!(dbase.find(&conf), None);
+    conf.under = Some(dir.join("pro"));
+    assert_eq!(dbase.find(&conf), None);
+}
+
+#[test]
+fn test_project_preference() {
+    let (dir, dbase) = make_tree_db(
+        "project",
+        &["mine/src", "theirs/src", "theirs/src", "theirs/Src"],
+    );
+    let mut conf = make_config();
+    fs::write(dir.join("mine/Cargo.toml"), "").unwrap();
+    conf.from = Some(dir.join("mine/src"));
+    conf.arguments = vec![OsString::from("src")];
+
+    // the best score, until the project is preferred
+    assert_eq!(dbase.find(&conf), Some(dir.join("theirs/src").as_path()));
+    assert_eq!(rasdf::project_root(&conf), None);
+    conf.project = true;
+    assert_eq!(rasdf::project_root(&conf), Some(dir.join("mine")));
+    assert_eq!(dbase.find(&conf), Some(dir.join("mine/src").as_path()));
+    let found = dbase.find_list(&conf);
+    assert_eq!(found.len(), 3);
+    assert_eq!(found.last().unwrap().0, dir.join("mine/src"));
+
+    // a preference, not a filter, so it goes along with strict and case
+    conf.case = config::Case::Sensitive;
+    conf.arguments = vec![OsString::from("Src")];
+    assert_eq!(dbase.find(&conf), Some(dir.join("theirs/Src").as_path()));
+    conf.strict = true;
+    conf.arguments = vec![OsString::from("mi")];
+    assert_eq!(dbase.find(&conf), None);
+    conf.strict = false;
+    assert_eq!(dbase.find(&conf), Some(dir.join("mine/src").as_path()));
+
+    // and within a subtree, outside the project
+    conf.case = config::Case::Insensitive;
+    conf.arguments = vec![OsString::from("src")];
+    conf.under = Some(dir.join("theirs"));
+    assert_eq!(dbase.find_list(&conf).len(), 2);
+    assert_eq!(dbase.find(&conf), Some(dir.join("theirs/src").as_path()));
+}
+
+#[test]
+fn test_config_settings() {
+    let mut conf = make_config();
+    let file = config::Source::File(PathBuf::from("config.toml"));
+
+    // later sources override earlier ones, and are recorded
+    let parsed = config_file::parse("maxlines = 50\nflags = \"fi\"\nignore = ['/tmp/**']\n").unwrap();
+    for (key, value) in &parsed {
+        conf.set(key, value, file.clone()).unwrap();
+    }
+    conf.set("maxlines", &config_file::Value::String("70".to_string()), config::Source::Env("RASDF_MAXLINES"))
+        .unwrap();
+    assert_eq!(conf.maxlines, 70);
+    assert!(conf.find_files && !conf.find_dirs);
+    assert_eq!(conf.sources["maxlines"], config::Source::Env("RASDF_MAXLINES"));
+    assert_eq!(conf.sources["types"], file);
+
+    let described = conf.describe();
+    assert!(described.contains("maxlines = 70  # env RASDF_MAXLINES\n"));
+    assert!(described.contains("types = \"files\"  # file config.toml\n"));
+    assert!(described.contains("ignore = [\"/tmp/**\"]  # file config.toml\n"));
+    assert!(described.contains("method = \"frecency\"  # default\n"));
+
+    assert!(conf.set("maxlines", &config_file::Value::Float(1.5), file.clone()).is_err());
+    assert!(conf.set("colour", &config_file::Value::Boolean(true), file).is_err());
+    assert_eq!(conf.maxlines, 70);
+}
+
+#[test]
+fn test_ignore_patterns() {
+    let dir = make_temp_dir("ignore");
+    let kept = dir.join("kept");
+    let skipped = dir.join("skipped");
+    fs::create_dir(&kept).unwrap();
+    fs::create_dir(&skipped).unwrap();
+
+    let mut conf = make_config();
+    conf.ignore = vec![String::from("**/skip*")];
+    let mut dbase = RasdfBase::new();
+    dbase.add_path(&conf, &kept).unwrap();
+    dbase.add_path(&conf, &skipped).unwrap();
+    assert_eq!(dbase.len(), 1);
+    assert!(dbase.entry(&kept).is_some());
+}
+
+#[test]
+fn test_score_weights() {
+    let mut conf = make_config();
+    conf.current_time = 10_000_000;
+    let recent = RasdfBaseData::new(&conf, Some(2.0), None, "");
+    let old = RasdfBaseData::new(&conf, Some(2.0), Some(0), "");
+    assert_eq!(recent.score(&conf), 12.0);
+    assert_eq!(old.score(&conf), 2.0);
+
+    conf.weights = vec![10.0, 5.0, 1.0, 0.5];
+    assert_eq!(recent.score(&conf), 20.0);
+    assert_eq!(old.score(&conf), 1.0);
+}
+
+#[test]
+fn test_decay_models() {
+    let mut conf = make_config();
+    conf.current_time = 10_000_000;
+    let day_old = RasdfBaseData::new(&conf, Some(2.0), Some(conf.current_time - 86400), "");
+
+    // two steps: within a minute, within a day, and older
+    let file = config::Source::File(PathBuf::from("config.toml"));
+    let parsed = config_file::parse("buckets = [60, 86400]\nweights = [3, 2, 0.5]\n").unwrap();
+    for (key, value) in &parsed {
+        conf.set(key, value, file.clone()).unwrap();
+    }
+    assert_eq!(day_old.score(&conf), 4.0);
+    conf.current_time += 1;
+    assert_eq!(day_old.score(&conf), 1.0);
+
+    conf.set("decay", &config_file::Value::String("exponential".to_string()), file.clone()).unwrap();
+    conf.half_life = 86401;
+    assert_eq!(day_old.score(&conf), 1.0);
+
+    // the fixed curves of zoxide and fasd differ at every age
+    conf.set("decay", &config_file::Value::String("zoxide".to_string()), file.clone()).unwrap();
+    assert_eq!(conf.decay, config::DecayModel::Zoxide);
+    assert_eq!(day_old.score(&conf), 1.0);
+    conf.set("decay", &config_file::Value::String("fasd".to_string()), file.clone()).unwrap();
+    assert_eq!(conf.decay, config::DecayModel::Fasd);
+    assert_eq!(day_old.score(&conf), 4.0);
+    conf.current_time -= 2;
+    assert_eq!(day_old.score(&conf), 8.0);
+    conf.decay = config::DecayModel::Zoxide;
+    assert_eq!(day_old.score(&conf), 4.0);
+
+    assert!(conf.set("buckets", &config_file::parse("b = [60, 60]").unwrap()[0].1, file.clone()).is_err());
+    assert!(conf.set("half_life", &config_file::Value::Integer(0), file.clone()).is_err());
+    assert!(conf.set("decay", &config_file::Value::String("linear".to_string()), file).is_err());
+}
+
+fn parse(args: &[&str]) -> Result<config::Config, String> {
+    let mut conf = make_config();
+    let args = std::iter::once("rasdf").chain(args.iter().copied()).map(OsString::from);
+    cli::parse_args(&mut conf, args)?;
+    Ok(conf)
+}
+
+#[test]
+fn test_parse_args() {
+    // options may come after the terms
+    let conf = parse(&["find", "foo", "-f", "--method=rating", "bar"]).unwrap();
+    assert_eq!(conf.command, "find");
+    assert_eq!(conf.arguments, vec![OsString::from("foo"), OsString::from("bar")]);
+    assert!(conf.find_files && !conf.find_dirs);
+    assert_eq!(conf.method, config::ScoreMethod::Rating);
+
+    let conf = parse(&["-l", "find-all", "--method", "date", "--", "-x"]).unwrap();
+    assert_eq!(conf.method, config::ScoreMethod::Date);
+    assert!(!conf.strict);
+    assert_eq!(conf.arguments, vec![OsString::from("-x")]);
+
+    // add takes a command line, so stops reading options at the first path
+    let conf = parse(&["add", "-a", "ls", "-l"]).unwrap();
+    assert!(conf.find_files && conf.find_dirs);
+    assert_eq!(conf.arguments, vec![OsString::from("ls"), OsString::from("-l")]);
+
+    assert_eq!(parse(&["find", "-z"]).unwrap().matching, config::Matching::Fuzzy);
+    assert_eq!(parse(&["find", "-S"]).unwrap().case, config::Case::Smart);
+    assert_eq!(parse(&["find", "--regex"]).unwrap().matching, config::Matching::Regex);
+    assert_eq!(parse(&["find", "-z", "--glob"]).unwrap().matching, config::Matching::Glob);
+    assert!(parse(&["find", "--glob=yes"]).is_err());
+    let conf = parse(&["find", "-g", "--anchor-first"]).unwrap();
+    assert!(conf.segments && conf.anchor_first);
+    assert!(!parse(&["find", "-gG"]).unwrap().segments);
+    let conf = parse(&["find", "-bu", "--from", "~/x", "--under=/tmp"]).unwrap();
+    assert!(conf.basename && conf.ancestors);
+    assert!(conf.from.unwrap().ends_with("x"));
+    assert_eq!(conf.under, Some(PathBuf::from("/tmp")));
+    assert_eq!(parse(&["find", "--smart-case", "-i"]).unwrap().case, config::Case::Insensitive);
+    let conf = parse(&["find", "--fuzzy", "--matching=literal"]).unwrap();
+    assert_eq!(conf.matching, config::Matching::Literal);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_parse_non_utf8_dirs() {
+    use std::os::unix::ffi::OsStringExt;
+
+    // folders need not be valid UTF-8, in either form of the option
+    let dir = OsString::from_vec(b"/tmp/caf\xe9".to_vec());
+    let mut under = OsString::from("--under=");
+    under.push(&dir);
+    let args = ["rasdf", "find", "-u", "--from"].iter().map(OsString::from);
+    let args = args.chain(vec![dir.clone(), under, OsString::from("x")]);
+    let mut conf = make_config();
+    cli::parse_args(&mut conf, args).unwrap();
+    assert_eq!(conf.from, Some(PathBuf::from(&dir)));
+    assert_eq!(conf.under, Some(PathBuf::from(&dir)));
+    assert_eq!(conf.arguments, vec![OsString::from("x")]);
+    assert!(parse(&["find", "--under"]).is_err());
+}
+
+#[test]
+fn test_parse_args_errors() {
+    assert_eq!(parse(&["find", "-x"]).err().unwrap(), "unrecognised option -x");
+    assert_eq!(parse(&["find", "--colour"]).err().unwrap(), "unrecognised option --colour");
+    assert!(parse(&["find", "--method"]).is_err());
+    assert!(parse(&["find", "--method=often"]).is_err());
+    assert!(parse(&["find", "--dirs=yes"]).is_err());
+    assert_eq!(parse(&["fnid", "foo"]).err().unwrap(), "not a valid command <fnid>");
+
+    // --help asks for the help on the command
+    let conf = parse(&["find", "foo", "--help"]).unwrap();
+    assert_eq!(conf.command, "help");
+    assert_eq!(conf.arguments, vec![OsString::from("find")]);
+    assert!(parse(&["-h"]).unwrap().arguments.is_empty());
+
+    // no terms at all is not a panic
+    let conf = parse(&["find", "-s"]).unwrap();
+    let dir = make_temp_dir("no-terms");
+    let mut dbase = RasdfBase::new();
+    dbase.add_path(&conf, &dir).unwrap();
+    assert_eq!(dbase.find(&conf), Some(dir.as_path()));
+}